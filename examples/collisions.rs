@@ -0,0 +1,153 @@
+// Collision benchmark, run with `cargo run --release --example collisions`,
+// followed by `-- <config file>` to measure another config. Both teams get
+// the same number of bullets between the players, and the cost of the
+// bullet-bullet check and of a whole tick is printed by bullet count, next to
+// the nested loop the grid replaced. Then the peak use of the bullet pools is
+// measured over long matches.
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use raspi_work::collision::{Collider, SpatialHash};
+use raspi_work::config::{self, config, Config, PoolOverflow};
+use raspi_work::constant::COLLISION_CELL_SIZE;
+use raspi_work::game::{GameState, Inputs};
+use raspi_work::object::{Arena, Bullets, Gun, Guns};
+use std::{
+    env,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+// Bullets of each team
+const BULLET_COUNTS: [usize; 6] = [10, 50, 200, 1000, 5000, 20000];
+const ROUNDS: u32 = 20;
+const MATCHES: u64 = 3;
+const MATCH_TICKS: u128 = 10_000;
+// Players keep this far from the bullets, so nobody is hit and the match
+// goes on
+const PLAYER_CLEARANCE: i32 = 8;
+
+fn main() -> anyhow::Result<()> {
+    if let Some(path) = env::args().nth(1) {
+        config::init(Config::load(Path::new(&path))?);
+    }
+    let config = config();
+    let arena = Arena::new(config.display.size.size());
+    let key_bindings = config.key_bindings();
+    let mut rng = StdRng::seed_from_u64(0);
+
+    println!(
+        "{:>8} {:>14} {:>14} {:>14}",
+        "bullets", "nested loop", "grid", "tick"
+    );
+    for count in BULLET_COUNTS {
+        let state = GameState::new(&key_bindings, arena, 0);
+        let min_x = state.players.player1.get_position().x + PLAYER_CLEARANCE;
+        let max_x = state.players.player2.get_position().x - PLAYER_CLEARANCE;
+        let mut guns = Guns::new(arena);
+        let mut shoot = |gun: &mut Gun| -> Bullets {
+            let mut bullets = Bullets::new(count, PoolOverflow::DropNew);
+            for _ in 0..count {
+                let x = rng.gen_range(min_x..=max_x);
+                bullets.spawn(gun.shoot(&mut rng, x));
+            }
+            bullets
+        };
+        let mono = shoot(&mut guns.gun1);
+        let di = shoot(&mut guns.gun2);
+        let mut collider = Collider::new(&state.players);
+
+        let nested = average(|| {
+            let (mut mono, mut di) = (mono.clone(), di.clone());
+            let started = Instant::now();
+            nested_loop(&collider, &mut mono, &mut di);
+            started.elapsed()
+        });
+        let mut hash = SpatialHash::new(COLLISION_CELL_SIZE);
+        let grid = average(|| {
+            let (mut mono, mut di) = (mono.clone(), di.clone());
+            let started = Instant::now();
+            hash.build(&di);
+            collider.collide_bullets(&hash, &mut mono, &mut di);
+            started.elapsed()
+        });
+        // The grid has to find the pairs the nested loop finds
+        let (mut nested_mono, mut nested_di) = (mono.clone(), di.clone());
+        nested_loop(&collider, &mut nested_mono, &mut nested_di);
+        let (mut grid_mono, mut grid_di) = (mono.clone(), di.clone());
+        hash.build(&grid_di);
+        collider.collide_bullets(&hash, &mut grid_mono, &mut grid_di);
+        assert!(
+            active(&grid_mono) == active(&nested_mono) && active(&grid_di) == active(&nested_di),
+            "the grid and the nested loop disagree with {count} bullets"
+        );
+        let tick = average(|| {
+            let mut state = GameState::new(&key_bindings, arena, 0);
+            state.bullets_mono = mono.clone();
+            state.bullets_di = di.clone();
+            let started = Instant::now();
+            state.step(&Inputs::default());
+            started.elapsed()
+        });
+        println!(
+            "{:>8} {:>12.1}us {:>12.1}us {:>12.1}us",
+            count,
+            micros(nested),
+            micros(grid),
+            micros(tick)
+        );
+    }
+
+    // Bullets in flight over a long match, hitpoints are refilled so it goes on
+    println!();
+    println!(
+        "{:>8} {:>8} {:>14} {:>10}",
+        "seed", "ticks", "peak bullets", "overflows"
+    );
+    for seed in 0..MATCHES {
+        let mut state = GameState::new(&key_bindings, arena, seed);
+        while state.tick < MATCH_TICKS {
+            state.mono_hitpoint = config.hitpoint.initial;
+            state.di_hitpoint = config.hitpoint.initial;
+            state.step(&Inputs::default());
+        }
+        let teams = [&state.bullets_mono, &state.bullets_di];
+        println!(
+            "{:>8} {:>8} {:>10}/{:<3} {:>10}",
+            seed,
+            state.tick,
+            teams.iter().map(|bullets| bullets.peak()).max().unwrap(),
+            state.bullets_mono.capacity(),
+            teams.iter().map(|bullets| bullets.overflows()).sum::<u64>()
+        );
+    }
+    Ok(())
+}
+
+// The bullet-bullet check before the grid
+fn nested_loop(collider: &Collider, mono: &mut Bullets, di: &mut Bullets) {
+    for m in 0..mono.capacity() {
+        for d in 0..di.capacity() {
+            let (m_bullet, d_bullet) = (&mono.slots()[m], &di.slots()[d]);
+            if m_bullet.active
+                && d_bullet.active
+                && collider.bullets_meet(&m_bullet.get_motion(), &d_bullet.get_motion())
+            {
+                mono.release(m);
+                di.release(d);
+            }
+        }
+    }
+}
+
+// Which slots hold a bullet in flight
+fn active(bullets: &Bullets) -> Vec<bool> {
+    bullets.slots().iter().map(|bullet| bullet.active).collect()
+}
+
+// Mean of `ROUNDS` runs, each timing itself
+fn average(mut run: impl FnMut() -> Duration) -> Duration {
+    (0..ROUNDS).map(|_| run()).sum::<Duration>() / ROUNDS
+}
+
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1e6
+}
@@ -0,0 +1,545 @@
+// Hit checks. Objects are hashed into a grid of square cells, so a check only
+// looks at the objects in the cells around a point instead of all of them, and
+// then sweeps the hitboxes derived from their sprites along their moves.
+use crate::config::{config, HitShape};
+use crate::object::{AbsoluteDirection, Bullet, Bullets, Motion, Player, Players, Position, Team};
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*, primitives::Rectangle};
+use std::convert::Infallible;
+
+// Buckets of the hash table, a power of two. Cells sharing a bucket only cost
+// a few extra exact checks.
+const BUCKETS: usize = 1024;
+
+pub struct SpatialHash {
+    cell_size: i32,
+    // Indices of the objects in the cells of a bucket, ascending
+    buckets: Vec<Vec<usize>>,
+}
+impl SpatialHash {
+    pub fn new(cell_size: i32) -> Self {
+        Self {
+            cell_size: cell_size.max(1),
+            buckets: vec![Vec::new(); BUCKETS],
+        }
+    }
+
+    fn cell(&self, x: i32, y: i32) -> (i32, i32) {
+        (x.div_euclid(self.cell_size), y.div_euclid(self.cell_size))
+    }
+
+    fn bucket(cell: (i32, i32)) -> usize {
+        let hash =
+            (cell.0 as u32).wrapping_mul(73_856_093) ^ (cell.1 as u32).wrapping_mul(19_349_663);
+        hash as usize % BUCKETS
+    }
+
+    // Hash the active bullets by their index, replacing what was there. The
+    // buckets keep their memory, so this does not allocate on every tick.
+    pub fn build(&mut self, bullets: &Bullets) {
+        for indices in &mut self.buckets {
+            indices.clear();
+        }
+        for (i, bullet) in bullets.slots().iter().enumerate() {
+            if bullet.active {
+                let position = bullet.get_position();
+                let bucket = Self::bucket(self.cell(position.x, position.y));
+                self.buckets[bucket].push(i);
+            }
+        }
+    }
+
+    // Put the indices of the objects which may be within `distance` of
+    // `center` on both axes into `found`, ascending
+    pub fn query(&self, center: &Position, distance: i32, found: &mut Vec<usize>) {
+        let (min_x, min_y) = self.cell(center.x - distance, center.y - distance);
+        let (max_x, max_y) = self.cell(center.x + distance, center.y + distance);
+        found.clear();
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                found.extend(&self.buckets[Self::bucket((x, y))]);
+            }
+        }
+        found.sort_unstable();
+        found.dedup();
+    }
+}
+
+// Pixels an object hits with, relative to its position
+pub struct Hitbox {
+    pixels: Vec<Point>,
+    // Corners of the box around `pixels`
+    min: Point,
+    max: Point,
+}
+impl Hitbox {
+    // Hitbox of the sprite `draw` draws for an object at `position`
+    pub fn new(
+        shape: HitShape,
+        position: &Position,
+        draw: impl FnOnce(&mut SpritePixels) -> Result<(), Infallible>,
+    ) -> Self {
+        let mut sprite = SpritePixels(Vec::new());
+        draw(&mut sprite).unwrap();
+        let origin = Point::new(position.x, position.y);
+        let mut pixels: Vec<Point> = sprite.0.iter().map(|pixel| *pixel - origin).collect();
+        pixels.sort_by_key(|pixel| (pixel.y, pixel.x));
+        pixels.dedup();
+        let min = pixels
+            .iter()
+            .fold(Point::zero(), |min, pixel| min.component_min(*pixel));
+        let max = pixels
+            .iter()
+            .fold(Point::zero(), |max, pixel| max.component_max(*pixel));
+        let pixels = match shape {
+            HitShape::Point => vec![Point::zero()],
+            HitShape::Box => Rectangle::with_corners(min, max).points().collect(),
+            HitShape::Mask => pixels,
+        };
+        let (min, max) = match shape {
+            HitShape::Point => (Point::zero(), Point::zero()),
+            HitShape::Box | HitShape::Mask => (min, max),
+        };
+        Self { pixels, min, max }
+    }
+
+    // Farthest a pixel is from the position on either axis
+    fn reach(&self) -> i32 {
+        [self.min.x, self.min.y, self.max.x, self.max.y]
+            .into_iter()
+            .map(i32::abs)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Whether this moving along `motion` comes within `distance` of `other`
+    // moving along `other_motion` during a tick. Both move at an even pace, so
+    // the offset between them is checked at every pixel it passes and nothing
+    // is skipped however fast they are.
+    pub fn hits(
+        &self,
+        motion: &Motion,
+        other: &Hitbox,
+        other_motion: &Motion,
+        distance: i32,
+    ) -> bool {
+        let start = offset(&motion.from, &other_motion.from);
+        let end = offset(&motion.to, &other_motion.to);
+        let delta = end - start;
+        let steps = delta.x.abs().max(delta.y.abs());
+        (0..=steps).any(|step| {
+            let offset = if steps == 0 {
+                start
+            } else {
+                start + delta * step / steps
+            };
+            self.hits_at(offset, other, distance)
+        })
+    }
+
+    // Whether a pixel of this is within `distance` of a pixel of `other` at
+    // `offset` from it, in Manhattan distance
+    fn hits_at(&self, offset: Point, other: &Hitbox, distance: i32) -> bool {
+        // the boxes have to be close first
+        let (other_min, other_max) = (other.min + offset, other.max + offset);
+        if other_min.x > self.max.x + distance
+            || other_max.x < self.min.x - distance
+            || other_min.y > self.max.y + distance
+            || other_max.y < self.min.y - distance
+        {
+            return false;
+        }
+        self.pixels.iter().any(|pixel| {
+            other.pixels.iter().any(|other_pixel| {
+                let gap = *other_pixel + offset - *pixel;
+                gap.x.abs() + gap.y.abs() <= distance
+            })
+        })
+    }
+}
+
+// Offset of `to` from `from`
+fn offset(from: &Position, to: &Position) -> Point {
+    Point::new(to.x - from.x, to.y - from.y)
+}
+
+// How far an object moved on either axis
+fn travel(motion: &Motion) -> i32 {
+    let delta = offset(&motion.from, &motion.to);
+    delta.x.abs().max(delta.y.abs())
+}
+
+// Draw target recording the pixels a sprite turns on
+pub struct SpritePixels(Vec<Point>);
+impl DrawTarget for SpritePixels {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if color.is_on() {
+                self.0.push(point);
+            }
+        }
+        Ok(())
+    }
+}
+impl OriginDimensions for SpritePixels {
+    // Sprites are recorded wherever they are drawn
+    fn size(&self) -> Size {
+        Size::new(u32::MAX, u32::MAX)
+    }
+}
+
+// Hitboxes of the objects of a match from `config().hit`
+pub struct Collider {
+    player1: Hitbox,
+    player2: Hitbox,
+    // Bullets fly away from their player, so the hitbox is the same for the
+    // bullets of a team
+    bullet_mono: Hitbox,
+    bullet_di: Hitbox,
+    distance: i32,
+    // Farthest a bullet moves in a tick
+    bullet_speed: i32,
+    // Indices found by the hash, kept so checks do not allocate
+    found: Vec<usize>,
+}
+impl Collider {
+    pub fn new(players: &Players) -> Self {
+        let hit = &config().hit;
+        let player = |player: &Player| {
+            Hitbox::new(hit.player, &player.get_position(), |sprite| {
+                player.draw(sprite)
+            })
+        };
+        let bullet = |direction| {
+            let bullet = Bullet::new(0, 0, direction);
+            Hitbox::new(hit.bullet, &bullet.get_position(), |sprite| {
+                bullet.draw(sprite)
+            })
+        };
+        Self {
+            player1: player(&players.player1),
+            player2: player(&players.player2),
+            bullet_mono: bullet(AbsoluteDirection::XPlus),
+            bullet_di: bullet(AbsoluteDirection::XMinus),
+            distance: hit.distance,
+            bullet_speed: config().shoot.bullet_speed,
+            found: Vec::new(),
+        }
+    }
+
+    // Release the active bullets of the opponent hitting the player of
+    // `team` on its `motion` and return how many there were. `hash` holds
+    // `bullets`.
+    pub fn hit_player(
+        &mut self,
+        team: Team,
+        motion: &Motion,
+        hash: &SpatialHash,
+        bullets: &mut Bullets,
+    ) -> usize {
+        let (player, bullet_hitbox) = match team {
+            Team::Mono => (&self.player1, &self.bullet_di),
+            Team::Di => (&self.player2, &self.bullet_mono),
+        };
+        let mut hits = 0;
+        let reach = player.reach()
+            + bullet_hitbox.reach()
+            + self.distance
+            + travel(motion)
+            + self.bullet_speed;
+        hash.query(&motion.to, reach, &mut self.found);
+        for &i in &self.found {
+            let bullet = &bullets.slots()[i];
+            if bullet.active
+                && player.hits(motion, bullet_hitbox, &bullet.get_motion(), self.distance)
+            {
+                bullets.release(i);
+                hits += 1;
+            }
+        }
+        hits
+    }
+
+    // Bullets of the two teams which meet destroy each other, a mono bullet
+    // takes the first di bullet it meets. `hash` holds `di`.
+    pub fn collide_bullets(&mut self, hash: &SpatialHash, mono: &mut Bullets, di: &mut Bullets) {
+        let mut found = std::mem::take(&mut self.found);
+        let reach = self.bullet_mono.reach()
+            + self.bullet_di.reach()
+            + self.distance
+            + 2 * self.bullet_speed;
+        for m in 0..mono.capacity() {
+            if !mono.slots()[m].active {
+                continue;
+            }
+            let m_motion = mono.slots()[m].get_motion();
+            hash.query(&m_motion.to, reach, &mut found);
+            for &d in &found {
+                let d_bullet = &di.slots()[d];
+                if d_bullet.active && self.bullets_meet(&m_motion, &d_bullet.get_motion()) {
+                    mono.release(m);
+                    di.release(d);
+                    break;
+                }
+            }
+        }
+        self.found = found;
+    }
+
+    // Whether a mono and a di bullet meet on their motions
+    pub fn bullets_meet(&self, mono: &Motion, di: &Motion) -> bool {
+        self.bullet_mono
+            .hits(mono, &self.bullet_di, di, self.distance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PoolOverflow;
+    use crate::constant::COLLISION_CELL_SIZE;
+    use crate::object::Arena;
+    use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    // 7x7 player square at the origin
+    fn player(shape: HitShape) -> Hitbox {
+        Hitbox::new(shape, &Position { x: 0, y: 0 }, |sprite| {
+            Rectangle::new(Point::new(-3, -3), Size::new(7, 7))
+                .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+                .draw(sprite)
+        })
+    }
+
+    // Bullet flying in `direction`, its tip 2px ahead of its position
+    fn bullet(shape: HitShape, direction: AbsoluteDirection) -> Hitbox {
+        let bullet = Bullet::new(0, 0, direction);
+        Hitbox::new(shape, &bullet.get_position(), |sprite| bullet.draw(sprite))
+    }
+
+    fn still(x: i32, y: i32) -> Motion {
+        moving((x, y), (x, y))
+    }
+
+    fn moving(from: (i32, i32), to: (i32, i32)) -> Motion {
+        Motion {
+            from: Position {
+                x: from.0,
+                y: from.1,
+            },
+            to: Position { x: to.0, y: to.1 },
+        }
+    }
+
+    #[test]
+    fn bullet_on_player_square() {
+        // Bullet position and whether the point, box and mask hit at distance
+        // 0 and 1
+        let cases = [
+            // the tip overlaps the right edge
+            ("edge", (5, 0), [[false, false], [true, true], [true, true]]),
+            // the tip is a pixel right of the edge
+            (
+                "edge miss",
+                (6, 0),
+                [[false, false], [false, true], [false, true]],
+            ),
+            // the corner of the bullet box overlaps the bottom right corner,
+            // the tip is a pixel below it
+            (
+                "corner",
+                (5, 4),
+                [[false, false], [true, true], [false, true]],
+            ),
+            // the bullet box is diagonal to the corner, 2px away
+            (
+                "corner miss",
+                (6, 5),
+                [[false, false], [false, false], [false, false]],
+            ),
+            // the centres are a pixel apart
+            (
+                "centre",
+                (1, 0),
+                [[false, true], [true, true], [true, true]],
+            ),
+        ];
+        for (name, (x, y), expected) in cases {
+            let shapes = [HitShape::Point, HitShape::Box, HitShape::Mask];
+            for (shape, expected) in shapes.into_iter().zip(expected) {
+                for (distance, expected) in expected.into_iter().enumerate() {
+                    let distance = distance as i32;
+                    let hits = player(shape).hits(
+                        &still(0, 0),
+                        &bullet(shape, AbsoluteDirection::XMinus),
+                        &still(x, y),
+                        distance,
+                    );
+                    assert_eq!(hits, expected, "{name} {shape:?} at distance {distance}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bullets_crossing_between_ticks() {
+        let mono = bullet(HitShape::Point, AbsoluteDirection::XPlus);
+        let di = bullet(HitShape::Point, AbsoluteDirection::XMinus);
+        // Mono and di motion, the gap is odd and then even
+        let cases = [((0, 2), (3, 1)), ((0, 3), (4, 1))];
+        for ((mono_from, mono_to), (di_from, di_to)) in cases {
+            let mono_motion = moving((mono_from, 0), (mono_to, 0));
+            let di_motion = moving((di_from, 0), (di_to, 0));
+            // They pass each other without ever sharing a pixel at the ends
+            assert!(!mono.hits(&still(mono_from, 0), &di, &still(di_from, 0), 0));
+            assert!(!mono.hits(&still(mono_to, 0), &di, &still(di_to, 0), 0));
+            assert!(
+                mono.hits(&mono_motion, &di, &di_motion, 0),
+                "mono {mono_from}->{mono_to} di {di_from}->{di_to}"
+            );
+        }
+    }
+
+    #[test]
+    fn fast_bullet_through_player() {
+        // 12px in a tick, more than the 7px of the player square
+        let shapes = [HitShape::Point, HitShape::Box, HitShape::Mask];
+        for shape in shapes {
+            let bullet = bullet(shape, AbsoluteDirection::XMinus);
+            assert!(!player(shape).hits(&still(0, 0), &bullet, &still(6, 0), 0));
+            assert!(!player(shape).hits(&still(0, 0), &bullet, &still(-6, 0), 0));
+            let through = moving((6, 0), (-6, 0));
+            assert!(
+                player(shape).hits(&still(0, 0), &bullet, &through, 0),
+                "{shape:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn fast_bullet_near_miss() {
+        // Passing a pixel below the player square
+        let shapes = [HitShape::Point, HitShape::Box, HitShape::Mask];
+        for shape in shapes {
+            let bullet = bullet(shape, AbsoluteDirection::XMinus);
+            let past = moving((10, 5), (-10, 5));
+            assert!(
+                !player(shape).hits(&still(0, 0), &bullet, &past, 0),
+                "{shape:?}"
+            );
+        }
+        // and within a distance of 1
+        let (player, bullet) = (
+            player(HitShape::Box),
+            bullet(HitShape::Box, AbsoluteDirection::XMinus),
+        );
+        assert!(player.hits(&still(0, 0), &bullet, &moving((10, 5), (-10, 5)), 1));
+    }
+
+    // On the borders of the cells and a pixel either side, negative and off
+    // the arena, or anywhere around it
+    fn coordinate(rng: &mut StdRng, size: i32) -> i32 {
+        let cell = COLLISION_CELL_SIZE;
+        match rng.gen_range(0..4) {
+            0 => rng.gen_range(-4..=4) * cell + rng.gen_range(-1..=1),
+            1 => rng.gen_range(size - cell..size + 3 * cell),
+            2 => rng.gen_range(-3 * cell..0),
+            _ => rng.gen_range(-3 * cell..size + 3 * cell),
+        }
+    }
+
+    // Bullets of `direction` around the cell borders, some moved a tick
+    fn scatter(rng: &mut StdRng, arena: &Arena, direction: AbsoluteDirection) -> Bullets {
+        let mut bullets = Bullets::new(60, PoolOverflow::DropNew);
+        for _ in 0..60 {
+            let (x, y) = (coordinate(rng, arena.width), coordinate(rng, arena.height));
+            bullets.spawn(Bullet::new(x, y, direction));
+        }
+        for bullet in bullets.slots_mut() {
+            if rng.gen_bool(0.5) {
+                bullet.tick();
+            }
+        }
+        bullets
+    }
+
+    fn active(bullets: &Bullets) -> Vec<bool> {
+        bullets.slots().iter().map(|bullet| bullet.active).collect()
+    }
+
+    #[test]
+    fn grid_finds_what_the_nested_loop_finds() {
+        let arena = Arena::new(config().display.size.size());
+        let players = Players::new(&config().key_bindings(), arena);
+        let mut collider = Collider::new(&players);
+        let mut hash = SpatialHash::new(COLLISION_CELL_SIZE);
+        let mut rng = StdRng::seed_from_u64(0);
+        let (mut bullet_hits, mut player_hits) = (0, 0);
+        for _ in 0..200 {
+            let mono = scatter(&mut rng, &arena, AbsoluteDirection::XPlus);
+            let di = scatter(&mut rng, &arena, AbsoluteDirection::XMinus);
+
+            // Bullets meeting bullets
+            let (mut grid_mono, mut grid_di) = (mono.clone(), di.clone());
+            hash.build(&grid_di);
+            collider.collide_bullets(&hash, &mut grid_mono, &mut grid_di);
+            let (mut nested_mono, mut nested_di) = (mono.clone(), di.clone());
+            for m in 0..nested_mono.capacity() {
+                for d in 0..nested_di.capacity() {
+                    let (m_bullet, d_bullet) = (&nested_mono.slots()[m], &nested_di.slots()[d]);
+                    if m_bullet.active
+                        && d_bullet.active
+                        && collider.bullets_meet(&m_bullet.get_motion(), &d_bullet.get_motion())
+                    {
+                        nested_mono.release(m);
+                        nested_di.release(d);
+                        bullet_hits += 1;
+                    }
+                }
+            }
+            assert_eq!(active(&grid_mono), active(&nested_mono));
+            assert_eq!(active(&grid_di), active(&nested_di));
+
+            // Bullets hitting the player, who may be moving
+            let from = Position {
+                x: coordinate(&mut rng, arena.width),
+                y: coordinate(&mut rng, arena.height),
+            };
+            let to = Position {
+                x: from.x + rng.gen_range(-2..=2),
+                y: from.y + rng.gen_range(-2..=2),
+            };
+            let motion = Motion { from, to };
+            let mut grid_di = di.clone();
+            hash.build(&grid_di);
+            let hits = collider.hit_player(Team::Mono, &motion, &hash, &mut grid_di);
+            let mut nested_di = di.clone();
+            let mut nested_hits = 0;
+            for d in 0..nested_di.capacity() {
+                let bullet = &nested_di.slots()[d];
+                if bullet.active
+                    && collider.player1.hits(
+                        &motion,
+                        &collider.bullet_di,
+                        &bullet.get_motion(),
+                        collider.distance,
+                    )
+                {
+                    nested_di.release(d);
+                    nested_hits += 1;
+                }
+            }
+            assert_eq!(hits, nested_hits);
+            assert_eq!(active(&grid_di), active(&nested_di));
+            player_hits += hits;
+        }
+        // Enough pairs met for the comparison to mean something
+        assert!(bullet_hits > 100, "{bullet_hits} bullet hits");
+        assert!(player_hits > 100, "{player_hits} player hits");
+    }
+}
@@ -0,0 +1,581 @@
+// Settings loaded from a TOML file. Every value defaults to `constant.rs`, so
+// a file only needs the values it changes.
+use crate::constant::{
+    BULLET_DAMEGE, BULLET_POOL_CAPACITY, BULLET_SPEED, BUTTON1_PIN, BUTTON2_PIN, CLEAN_INTERVAL,
+    DC_PIN, DEBOUNCE_MS, DISPLAY_MARGIN, DISPLAY_SIZE_X, DISPLAY_SIZE_Y, EMIT_PROBABILITY,
+    EMIT_TICK_SIZE, HIT_DISTANCE, HIT_MELODY, INITIAL_HITPOINT, LASER_MELODY, LASER_SPAWN_POSITION,
+    LED1_PIN, LED2_PIN, LONG_PRESS_MS, LOSE_MELODY, RESULT_TICK_SIZE, RST_PIN, SHOOT_INTERVAL,
+    SHOT_MELODY, SPI_CLOCK_HZ, START_MELODY, TICK_SIZE, WIN_MELODY,
+};
+use crate::key_binding::{KeyBindings, PlayerKeysConfig};
+use crate::sound::parse_rtttl;
+use anyhow::{bail, ensure, Context};
+use embedded_graphics::prelude::Size;
+use serde::Deserialize;
+use std::{
+    fs,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::Duration,
+};
+
+// Used when `--config` is not given and the file exists
+pub const DEFAULT_CONFIG_PATH: &str = "vshooter.toml";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+// Loaded config, or the defaults before it is loaded
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+// Make `config` the loaded config. Must be called before the first `config()`.
+pub fn init(config: Config) {
+    if CONFIG.set(config).is_err() {
+        panic!("config is already initialized");
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub display: Panel,
+    pub pins: Pins,
+    pub buttons: ButtonTiming,
+    pub tick: Tick,
+    pub hitpoint: Hitpoint,
+    pub hit: Hit,
+    pub shoot: Shoot,
+    pub laser: Laser,
+    pub sound: Sound,
+    pub random: Random,
+    pub keys: Keys,
+    // File the config was loaded from
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let mut config: Self =
+            toml::from_str(&text).with_context(|| format!("invalid {}", path.display()))?;
+        config.path = Some(path.to_path_buf());
+        config
+            .validate()
+            .with_context(|| format!("invalid {}", path.display()))?;
+        Ok(config)
+    }
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut pins = vec![
+            ("pins.button1", self.pins.button1),
+            ("pins.button2", self.pins.button2),
+            ("pins.led1", self.pins.led1),
+            ("pins.led2", self.pins.led2),
+        ];
+        if let Some(buzzer) = self.pins.buzzer {
+            pins.push(("pins.buzzer", buzzer));
+        }
+        // The DC and RST pins are only wired for SPI panels
+        if self.display.interface == Bus::Spi {
+            ensure!(
+                self.display.controller == Controller::Ssd1306,
+                "display.interface = \"spi\" is only supported with the ssd1306 controller"
+            );
+            check_range(
+                "display.spi_clock_hz",
+                self.display.spi_clock_hz,
+                100_000..=32_000_000,
+            )?;
+            pins.push(("pins.dc", self.pins.dc));
+            pins.push(("pins.rst", self.pins.rst));
+            // CE0, MOSI and SCLK of SPI0
+            if let Some((name, pin)) = pins.iter().find(|(_, pin)| [8, 10, 11].contains(pin)) {
+                bail!("{name} uses GPIO {pin} which is used by SPI0");
+            }
+        }
+        for (i, (name, pin)) in pins.iter().enumerate() {
+            check_range(name, *pin, 0..=27)?;
+            if let Some((other, _)) = pins[..i].iter().find(|(_, other)| other == pin) {
+                bail!("{name} uses GPIO {pin} which is already used by {other}");
+            }
+        }
+        check_range("buttons.debounce_ms", self.buttons.debounce_ms, 0..=1000)?;
+        check_range(
+            "buttons.long_press_ms",
+            self.buttons.long_press_ms,
+            self.buttons.debounce_ms..=60_000,
+        )?;
+        check_range("tick.tick_ms", self.tick.tick_ms, 1..=1000)?;
+        check_range("tick.clean_interval", self.tick.clean_interval, 1..=u8::MAX)?;
+        check_range("tick.result_ticks", self.tick.result_ticks, 0..=10_000)?;
+        check_range("hitpoint.initial", self.hitpoint.initial, 1..=i8::MAX)?;
+        check_range(
+            "hitpoint.bullet_damage",
+            self.hitpoint.bullet_damage,
+            1..=self.hitpoint.initial,
+        )?;
+        check_range("hit.distance", self.hit.distance, 0..=DISPLAY_SIZE_Y)?;
+        check_range(
+            "hit.display_margin",
+            self.hit.display_margin,
+            0..=DISPLAY_SIZE_X,
+        )?;
+        // The shooting interval is counted down in a `u8`
+        check_range(
+            "shoot.interval",
+            self.shoot.interval,
+            1..=100 * u8::MAX as u64,
+        )?;
+        check_range(
+            "shoot.bullet_speed",
+            self.shoot.bullet_speed,
+            1..=DISPLAY_SIZE_X,
+        )?;
+        check_range("shoot.pool_capacity", self.shoot.pool_capacity, 1..=10_000)?;
+        check_range("laser.emit_ticks", self.laser.emit_ticks, 0..=1000)?;
+        check_range(
+            "laser.spawn_position",
+            self.laser.spawn_position,
+            0..=DISPLAY_SIZE_X,
+        )?;
+        check_range(
+            "laser.emit_probability",
+            self.laser.emit_probability,
+            1..=i32::MAX,
+        )?;
+        for (name, melody) in self.sound.melodies() {
+            parse_rtttl(melody).with_context(|| format!("invalid sound.{name}"))?;
+        }
+        self.key_bindings().validate()?;
+        Ok(())
+    }
+    // File which settings changed on the device are saved to
+    pub fn save_path(&self) -> PathBuf {
+        self.path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH))
+    }
+    pub fn key_bindings(&self) -> KeyBindings {
+        KeyBindings::new(&self.keys.player1, &self.keys.player2)
+    }
+}
+
+fn check_range<T>(name: &str, value: T, range: RangeInclusive<T>) -> anyhow::Result<()>
+where
+    T: PartialOrd + std::fmt::Display,
+{
+    ensure!(
+        range.contains(&value),
+        "{name} must be between {} and {}, but it is {value}",
+        range.start(),
+        range.end()
+    );
+    Ok(())
+}
+
+// Display
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Panel {
+    pub controller: Controller,
+    pub size: PanelSize,
+    pub interface: Bus,
+    pub spi_clock_hz: u32,
+    pub flush: Flush,
+}
+impl Default for Panel {
+    fn default() -> Self {
+        Self {
+            controller: Controller::default(),
+            size: PanelSize::default(),
+            interface: Bus::default(),
+            spi_clock_hz: SPI_CLOCK_HZ,
+            flush: Flush::default(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bus {
+    #[default]
+    I2c,
+    // SPI0 with CE0 as chip select
+    Spi,
+}
+
+// What an SSD1306 flush sends
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Flush {
+    // The columns of the pages which changed since the last flush
+    #[default]
+    Changed,
+    // The whole screen
+    Full,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Controller {
+    #[default]
+    Ssd1306,
+    Sh1106,
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize)]
+pub enum PanelSize {
+    #[default]
+    #[serde(rename = "128x64")]
+    Size128x64,
+    #[serde(rename = "128x32")]
+    Size128x32,
+}
+impl PanelSize {
+    pub fn size(&self) -> Size {
+        match self {
+            PanelSize::Size128x64 => Size::new(DISPLAY_SIZE_X as u32, DISPLAY_SIZE_Y as u32),
+            PanelSize::Size128x32 => Size::new(DISPLAY_SIZE_X as u32, DISPLAY_SIZE_Y as u32 / 2),
+        }
+    }
+}
+
+// Pin
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Pins {
+    pub button1: u8,
+    pub button2: u8,
+    pub led1: u8,
+    pub led2: u8,
+    // SPI data/command and reset
+    pub dc: u8,
+    pub rst: u8,
+    // Piezo buzzer, silent without it
+    pub buzzer: Option<u8>,
+}
+impl Default for Pins {
+    fn default() -> Self {
+        Self {
+            button1: BUTTON1_PIN,
+            button2: BUTTON2_PIN,
+            led1: LED1_PIN,
+            led2: LED2_PIN,
+            dc: DC_PIN,
+            rst: RST_PIN,
+            buzzer: None,
+        }
+    }
+}
+
+// Button
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ButtonTiming {
+    pub debounce_ms: u64,
+    pub long_press_ms: u64,
+}
+impl ButtonTiming {
+    pub fn debounce(&self) -> Duration {
+        Duration::from_millis(self.debounce_ms)
+    }
+    pub fn long_press(&self) -> Duration {
+        Duration::from_millis(self.long_press_ms)
+    }
+}
+impl Default for ButtonTiming {
+    fn default() -> Self {
+        Self {
+            debounce_ms: DEBOUNCE_MS,
+            long_press_ms: LONG_PRESS_MS,
+        }
+    }
+}
+
+// Tick
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tick {
+    pub tick_ms: u64,
+    pub clean_interval: u8,
+    // Exit cool time
+    pub result_ticks: i32,
+    // Draw the measured tick and frame rates and the bullet pool peak
+    pub show_rates: bool,
+}
+impl Tick {
+    pub fn tick_size(&self) -> Duration {
+        Duration::from_millis(self.tick_ms)
+    }
+    // Ticks lasting `duration`, at least one
+    pub fn ticks(&self, duration: Duration) -> u64 {
+        (duration.as_millis() as u64 / self.tick_ms).max(1)
+    }
+}
+impl Default for Tick {
+    fn default() -> Self {
+        Self {
+            tick_ms: TICK_SIZE.as_millis() as u64,
+            clean_interval: CLEAN_INTERVAL,
+            result_ticks: RESULT_TICK_SIZE,
+            show_rates: false,
+        }
+    }
+}
+
+// Hitpoint
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hitpoint {
+    pub initial: i8,
+    pub bullet_damage: i8,
+}
+impl Default for Hitpoint {
+    fn default() -> Self {
+        Self {
+            initial: INITIAL_HITPOINT,
+            bullet_damage: BULLET_DAMEGE,
+        }
+    }
+}
+
+// What of an object's sprite can be hit
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HitShape {
+    // The centre pixel only
+    Point,
+    // The bounding box of the sprite
+    Box,
+    // The pixels of the sprite
+    #[default]
+    Mask,
+}
+
+// Hit
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hit {
+    // Manhattan distance between the shapes which still counts as a hit
+    pub distance: i32,
+    pub display_margin: i32,
+    pub player: HitShape,
+    pub bullet: HitShape,
+}
+impl Default for Hit {
+    fn default() -> Self {
+        Self {
+            distance: HIT_DISTANCE,
+            display_margin: DISPLAY_MARGIN,
+            player: HitShape::default(),
+            bullet: HitShape::default(),
+        }
+    }
+}
+
+// Shoot
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Shoot {
+    pub interval: u64,
+    // Pixels a bullet flies per tick
+    pub bullet_speed: i32,
+    // Bullets of a team in flight at once
+    pub pool_capacity: usize,
+    pub pool_overflow: PoolOverflow,
+}
+impl Default for Shoot {
+    fn default() -> Self {
+        Self {
+            interval: SHOOT_INTERVAL as u64,
+            bullet_speed: BULLET_SPEED,
+            pool_capacity: BULLET_POOL_CAPACITY,
+            pool_overflow: PoolOverflow::default(),
+        }
+    }
+}
+
+// What shooting does when every bullet of the team is in flight
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PoolOverflow {
+    // The bullet shot first makes way
+    #[default]
+    ReplaceOldest,
+    // The new bullet is not shot
+    DropNew,
+}
+
+// Laser
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Laser {
+    pub emit_ticks: i32,
+    pub spawn_position: i32,
+    pub emit_probability: i32,
+}
+impl Default for Laser {
+    fn default() -> Self {
+        Self {
+            emit_ticks: EMIT_TICK_SIZE,
+            spawn_position: LASER_SPAWN_POSITION,
+            emit_probability: EMIT_PROBABILITY,
+        }
+    }
+}
+
+// Sound
+// RTTTL melodies of the game events, `win` and `lose` are from the side of
+// player 1
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sound {
+    pub start: String,
+    pub shot: String,
+    pub hit: String,
+    pub laser: String,
+    pub win: String,
+    pub lose: String,
+}
+impl Sound {
+    fn melodies(&self) -> [(&str, &str); 6] {
+        [
+            ("start", &self.start),
+            ("shot", &self.shot),
+            ("hit", &self.hit),
+            ("laser", &self.laser),
+            ("win", &self.win),
+            ("lose", &self.lose),
+        ]
+    }
+}
+impl Default for Sound {
+    fn default() -> Self {
+        Self {
+            start: START_MELODY.to_string(),
+            shot: SHOT_MELODY.to_string(),
+            hit: HIT_MELODY.to_string(),
+            laser: LASER_MELODY.to_string(),
+            win: WIN_MELODY.to_string(),
+            lose: LOSE_MELODY.to_string(),
+        }
+    }
+}
+
+// Random
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Random {
+    // Seed of every match, a new one is drawn per match without it
+    pub seed: Option<u64>,
+}
+
+// Keys
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Keys {
+    pub player1: PlayerKeysConfig,
+    pub player2: PlayerKeysConfig,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> anyhow::Result<Config> {
+        let config: Config = toml::from_str(text)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Full error chain of an invalid config
+    fn invalid(text: &str) -> String {
+        format!("{:#}", parse(text).expect_err(text))
+    }
+
+    #[test]
+    fn empty_file_gives_defaults() {
+        let config = parse("").unwrap();
+        assert_eq!(config.display.controller, Controller::Ssd1306);
+        assert_eq!(config.display.interface, Bus::I2c);
+        assert_eq!(config.pins.button1, BUTTON1_PIN);
+        assert_eq!(config.pins.led2, LED2_PIN);
+        assert_eq!(config.pins.buzzer, None);
+        assert_eq!(config.buttons.debounce_ms, DEBOUNCE_MS);
+        assert_eq!(config.tick.tick_size(), TICK_SIZE);
+        assert_eq!(config.hitpoint.initial, INITIAL_HITPOINT);
+        assert_eq!(config.shoot.pool_capacity, BULLET_POOL_CAPACITY);
+        assert_eq!(config.laser.emit_probability, EMIT_PROBABILITY);
+        assert_eq!(config.sound.win, WIN_MELODY);
+        assert_eq!(config.random.seed, None);
+    }
+
+    #[test]
+    fn out_of_range_values() {
+        for (text, key) in [
+            ("[tick]\ntick_ms = 0", "tick.tick_ms"),
+            ("[tick]\ntick_ms = 1001", "tick.tick_ms"),
+            ("[tick]\nclean_interval = 0", "tick.clean_interval"),
+            ("[hitpoint]\ninitial = 0", "hitpoint.initial"),
+            (
+                "[hitpoint]\ninitial = 3\nbullet_damage = 4",
+                "hitpoint.bullet_damage",
+            ),
+            ("[hitpoint]\nbullet_damage = 0", "hitpoint.bullet_damage"),
+            ("[laser]\nemit_ticks = -1", "laser.emit_ticks"),
+            ("[laser]\nspawn_position = 129", "laser.spawn_position"),
+            ("[laser]\nemit_probability = 0", "laser.emit_probability"),
+            ("[pins]\nled1 = 28", "pins.led1"),
+        ] {
+            let error = invalid(text);
+            assert!(error.starts_with(key), "{text}: {error}");
+        }
+    }
+
+    #[test]
+    fn duplicate_pins() {
+        let error = invalid("[pins]\nled1 = 26");
+        assert!(
+            error.contains("pins.led1") && error.contains("pins.button1"),
+            "{error}"
+        );
+        let error = invalid("[pins]\nbuzzer = 4");
+        assert!(
+            error.contains("pins.buzzer") && error.contains("pins.button2"),
+            "{error}"
+        );
+        // DC and RST are only taken by SPI panels
+        parse("[pins]\nled1 = 24").unwrap();
+        let error = invalid("[display]\ninterface = \"spi\"\n[pins]\nled1 = 24");
+        assert!(
+            error.contains("pins.dc") && error.contains("pins.led1"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn spi0_pins() {
+        for (pins, key) in [
+            ("led1 = 8", "pins.led1"),
+            ("button2 = 10", "pins.button2"),
+            ("dc = 11", "pins.dc"),
+            ("buzzer = 8", "pins.buzzer"),
+        ] {
+            let text = format!("[display]\ninterface = \"spi\"\n[pins]\n{pins}");
+            let error = invalid(&text);
+            assert!(error.starts_with(key), "{text}: {error}");
+            assert!(error.contains("SPI0"), "{text}: {error}");
+            // Free while the panel is on I2C
+            parse(&format!("[pins]\n{pins}")).unwrap();
+        }
+    }
+
+    #[test]
+    fn sh1106_over_spi() {
+        let error = invalid("[display]\ncontroller = \"sh1106\"\ninterface = \"spi\"");
+        assert!(error.contains("display.interface"), "{error}");
+        parse("[display]\ncontroller = \"sh1106\"").unwrap();
+    }
+}
@@ -4,10 +4,21 @@ pub const BUTTON1_PIN: u8 = 26;
 pub const BUTTON2_PIN: u8 = 4;
 pub const LED1_PIN: u8 = 19;
 pub const LED2_PIN: u8 = 20;
+// SPI display
+pub const DC_PIN: u8 = 24;
+pub const RST_PIN: u8 = 25;
+
+// Button
+pub const DEBOUNCE_MS: u64 = 20;
+pub const LONG_PRESS_MS: u64 = 800;
 
 // Tick
 pub const TICK_SIZE: Duration = Duration::from_millis(3);
 pub const CLEAN_INTERVAL: u8 = 20;
+// Frames skipped in a row at most while the ticks are behind
+pub const MAX_FRAME_SKIP: u32 = 10;
+// Ticks further behind than this are dropped
+pub const MAX_LAG: Duration = Duration::from_millis(250);
 
 // Hitpoint
 pub const INITIAL_HITPOINT: i8 = 64;
@@ -16,18 +27,55 @@ pub const BULLET_DAMEGE: i8 = 4;
 // Display
 pub const DISPLAY_SIZE_X: i32 = 128;
 pub const DISPLAY_SIZE_Y: i32 = 64;
+pub const SPI_CLOCK_HZ: u32 = 8_000_000;
+
+// Arena
+pub const PLAYER_START_X: i32 = 10;
+// Half the gap between the areas of the players
+pub const CENTER_GAP: i32 = 14;
 
 // Hit
-pub const HIT_DISTANCE: i32 = 1;
+pub const HIT_DISTANCE: i32 = 0;
 pub const DISPLAY_MARGIN: i32 = 10;
+// Cells of the broad phase grid, in pixels
+pub const COLLISION_CELL_SIZE: i32 = 4;
 
 // Exit cool time
 pub const RESULT_TICK_SIZE: i32 = 100;
 
 // Shoot
 pub const SHOOT_INTERVAL: u128 = 1000;
+pub const BULLET_SPEED: i32 = 2;
+// Late in a match a gun fires every tick, about 50 bullets of a team are in
+// flight on a 128 wide display
+pub const BULLET_POOL_CAPACITY: usize = 256;
 
 // Laser
 pub const EMIT_TICK_SIZE: i32 = 20;
 pub const LASER_SPAWN_POSITION: i32 = 9;
 pub const EMIT_PROBABILITY: i32 = 280;
+
+// Sound, in RTTTL
+pub const START_MELODY: &str = "start:d=16,o=6,b=180:c,e,g,8c7";
+pub const SHOT_MELODY: &str = "shot:d=64,o=7,b=240:c";
+pub const HIT_MELODY: &str = "hit:d=32,o=4,b=200:c,p,c";
+pub const LASER_MELODY: &str = "laser:d=64,o=6,b=200:g7,f7,e7,d7,c7,b,a,g";
+pub const WIN_MELODY: &str = "win:d=8,o=6,b=160:c,e,g,c7,p,g,4c7";
+pub const LOSE_MELODY: &str = "lose:d=8,o=5,b=120:g,f#,f,2e";
+
+// Led, in ticks
+pub const START_COUNTDOWN_TICKS: u64 = 150;
+pub const START_COUNTDOWN_BEATS: u64 = 3;
+pub const WIN_BLINK_PERIOD: u64 = 100;
+pub const LOW_HITPOINT_BREATHE_PERIOD: u64 = 300;
+// Menus tick with the game, about 200 ms
+pub const MENU_BLINK_HALF_PERIOD: u64 = 67;
+
+// Scene
+pub const BOOT_RING_TIME: Duration = Duration::from_millis(1000);
+// Pause after the ring, both buttons or Tab held then open the key bindings
+pub const BOOT_HOLD_TIME: Duration = Duration::from_millis(500);
+// The title starts a match by itself when nothing is pressed for this long
+pub const TITLE_TIMEOUT: Duration = Duration::from_secs(5);
+// The results go back to the title after this
+pub const RESULTS_TIMEOUT: Duration = Duration::from_secs(5);
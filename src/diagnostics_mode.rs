@@ -0,0 +1,109 @@
+use crate::config::config;
+use crate::interface::{
+    debounce::{has_event, Button, ButtonEventKind},
+    led_pattern::Pattern,
+    Display, Interfaces, Keycodes,
+};
+use crate::scene::{fit_lines, Input, Press, Presses, Scene, SceneId, Session, Transition};
+use crate::sound::Effect;
+use device_query::Keycode;
+use display_interface::DisplayError;
+use embedded_graphics::{
+    pixelcolor::BinaryColor,
+    prelude::*,
+    text::{Baseline, Text},
+};
+use itertools::Itertools;
+
+// Diagnostics scene, to check the hardware
+// The LED of a button is lit while it is held and pressing it beeps. The keys
+// held, the rates and the display are shown. A long press of button 2 or
+// Escape goes back to the menu.
+pub struct DiagnosticsScene {
+    presses: Presses,
+    pressed: [bool; 2],
+    keycodes: Keycodes,
+    ticks: u64,
+}
+impl DiagnosticsScene {
+    pub fn new() -> Self {
+        Self {
+            presses: Presses::new(),
+            pressed: [false; 2],
+            keycodes: Keycodes::new(),
+            ticks: 0,
+        }
+    }
+}
+
+impl<D: Display> Scene<D> for DiagnosticsScene {
+    fn update(
+        &mut self,
+        interfaces: &mut Interfaces<D>,
+        _session: &mut Session,
+        input: &Input,
+    ) -> anyhow::Result<Transition> {
+        let [_, button2] = self.presses.read(&input.button_events);
+        if button2 == Press::Long || input.pressed.contains(&Keycode::Escape) {
+            return Ok(Transition::To(SceneId::Menu));
+        }
+        self.ticks += 1;
+        self.keycodes = input.keycodes.clone();
+        self.pressed = [Button::Button1, Button::Button2].map(|button| {
+            if has_event(&input.button_events, button, ButtonEventKind::Pressed) {
+                interfaces.sound.play(Effect::Shot);
+            }
+            interfaces.buttons.is_pressed(button)
+        });
+        let pattern = |pressed| if pressed { Pattern::On } else { Pattern::Off };
+        interfaces.leds.led1.set(pattern(self.pressed[0]));
+        interfaces.leds.led2.set(pattern(self.pressed[1]));
+        Ok(Transition::Stay)
+    }
+
+    fn render(&self, session: &Session, display: &mut D) -> Result<(), DisplayError> {
+        let level = |pressed| if pressed { "down" } else { "up" };
+        let panel = &config().display;
+        let size = display.size();
+        display.clear(BinaryColor::Off)?;
+        let lines = [
+            format!(
+                "B1 {}  B2 {}",
+                level(self.pressed[0]),
+                level(self.pressed[1])
+            ),
+            format!("keys {}", self.keycodes.iter().join(" ")),
+            format!(
+                "{:.0}t/s {:.0}f/s",
+                session.rates.ticks_per_sec, session.rates.frames_per_sec
+            ),
+            format!(
+                "{:?} {}x{} {:?}",
+                panel.controller, size.width, size.height, panel.interface
+            ),
+            format!("tick {}", self.ticks),
+        ];
+        let (character_style, row_height) = fit_lines(size.height, lines.len() as u32);
+        for (row, line) in lines.iter().enumerate() {
+            Text::with_baseline(
+                line,
+                Point::new(0, row as i32 * row_height),
+                character_style,
+                Baseline::Top,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn exit(
+        &mut self,
+        interfaces: &mut Interfaces<D>,
+        _session: &mut Session,
+    ) -> anyhow::Result<()> {
+        interfaces.leds.led1.set(Pattern::Off);
+        interfaces.leds.led2.set(Pattern::Off);
+        interfaces.leds.tick();
+        Ok(())
+    }
+}
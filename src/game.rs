@@ -0,0 +1,436 @@
+// State of a match, stepped one tick at a time without touching the hardware.
+// `render` draws it on any draw target.
+use crate::collision::{Collider, SpatialHash};
+use crate::config::config;
+use crate::constant::COLLISION_CELL_SIZE;
+use crate::interface::Keycodes;
+use crate::key_binding::KeyBindings;
+use crate::object::{Arena, Bullets, Guns, Lasers, Players, Team};
+use embedded_graphics::{
+    mono_font::{
+        ascii::{FONT_10X20, FONT_4X6},
+        MonoTextStyle,
+    },
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::{Line, PrimitiveStyleBuilder},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+};
+use rand::{rngs::StdRng, SeedableRng};
+
+// Inputs of one tick
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Inputs {
+    pub keycodes: Keycodes,
+    // Laser buttons pressed on this tick
+    pub laser1: bool,
+    pub laser2: bool,
+}
+
+// What happened on a tick
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GameEvent {
+    // Both guns fired
+    Shot,
+    // The player of the team was hit
+    Hit(Team),
+    LaserReady(Team),
+    LaserFired(Team),
+    // Both hitpoints ran out on the same tick, both are set to one bullet
+    SuddenDeath,
+    Won(Team),
+}
+
+pub struct GameState {
+    pub arena: Arena,
+    pub tick: u128,
+    pub mono_hitpoint: i8,
+    pub di_hitpoint: i8,
+    pub winner: Option<Team>,
+    pub players: Players,
+    pub lasers: Lasers,
+    pub bullets_mono: Bullets,
+    pub bullets_di: Bullets,
+    // All randomness of the match comes from `rng`, so a seed replays it
+    pub seed: u64,
+    rng: StdRng,
+    guns: Guns,
+    collider: Collider,
+    // Broad phase of the hit checks, rebuilt every tick
+    hash_mono: SpatialHash,
+    hash_di: SpatialHash,
+    shooting_interval: u8,
+    clean_interval: u8,
+    // Exit cool time, counted down once there is a winner
+    tick_for_exit: Option<i32>,
+    over: bool,
+}
+impl GameState {
+    pub fn new(key_bindings: &KeyBindings, arena: Arena, seed: u64) -> Self {
+        let config = config();
+        let players = Players::new(key_bindings, arena);
+        Self {
+            arena,
+            tick: 0,
+            mono_hitpoint: config.hitpoint.initial,
+            di_hitpoint: config.hitpoint.initial,
+            winner: None,
+            collider: Collider::new(&players),
+            players,
+            lasers: Lasers::new(arena),
+            bullets_mono: Bullets::new(config.shoot.pool_capacity, config.shoot.pool_overflow),
+            bullets_di: Bullets::new(config.shoot.pool_capacity, config.shoot.pool_overflow),
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            guns: Guns::new(arena),
+            hash_mono: SpatialHash::new(COLLISION_CELL_SIZE),
+            hash_di: SpatialHash::new(COLLISION_CELL_SIZE),
+            shooting_interval: 0,
+            clean_interval: config.tick.clean_interval,
+            tick_for_exit: None,
+            over: false,
+        }
+    }
+
+    // Whether the result has been shown long enough
+    pub fn is_over(&self) -> bool {
+        self.over
+    }
+
+    pub fn step(&mut self, inputs: &Inputs) -> Vec<GameEvent> {
+        let config = config();
+        let mut events = Vec::new();
+
+        // Tick
+        self.tick += 1;
+
+        // Process par tick
+        // player
+        self.players.player1.tick(&inputs.keycodes);
+        self.players.player2.tick(&inputs.keycodes);
+        // bullets
+        for bullet in self.bullets_mono.slots_mut() {
+            if bullet.active {
+                bullet.tick();
+            }
+        }
+        for bullet in self.bullets_di.slots_mut() {
+            if bullet.active {
+                bullet.tick();
+            }
+        }
+
+        // Get players position
+        let p1_pos = self.players.player1.get_position();
+        let p2_pos = self.players.player2.get_position();
+
+        // Shoot
+        if self.shooting_interval == 0 {
+            self.shooting_interval = (config.shoot.interval as u128 / (self.tick / 2 + 100)) as u8;
+            self.bullets_mono
+                .spawn(self.guns.gun1.shoot(&mut self.rng, p1_pos.x));
+            self.bullets_di
+                .spawn(self.guns.gun2.shoot(&mut self.rng, p2_pos.x));
+            events.push(GameEvent::Shot);
+        } else {
+            self.shooting_interval -= 1;
+        }
+
+        if self.winner.is_none() {
+            // Laser
+            let ready = (self.lasers.laser1.is_ready(), self.lasers.laser2.is_ready());
+            // laser1
+            if let Some(bullet) =
+                self.lasers
+                    .laser1
+                    .try_emit(&mut self.rng, p2_pos.y, inputs.laser1)
+            {
+                self.bullets_mono.spawn(bullet);
+            }
+            // laser2
+            if let Some(bullet) =
+                self.lasers
+                    .laser2
+                    .try_emit(&mut self.rng, p1_pos.y, inputs.laser2)
+            {
+                self.bullets_di.spawn(bullet);
+            }
+            for (team, was_ready, is_ready) in [
+                (Team::Mono, ready.0, self.lasers.laser1.is_ready()),
+                (Team::Di, ready.1, self.lasers.laser2.is_ready()),
+            ] {
+                match (was_ready, is_ready) {
+                    (false, true) => events.push(GameEvent::LaserReady(team)),
+                    (true, false) => events.push(GameEvent::LaserFired(team)),
+                    _ => (),
+                }
+            }
+
+            // Hit
+            self.hash_mono.build(&self.bullets_mono);
+            self.hash_di.build(&self.bullets_di);
+            // player and bullet
+            let hits = self.collider.hit_player(
+                Team::Mono,
+                &self.players.player1.get_motion(),
+                &self.hash_di,
+                &mut self.bullets_di,
+            );
+            // Saturating, a few hits of up to i8::MAX damage would overflow
+            for _ in 0..hits {
+                self.mono_hitpoint = self
+                    .mono_hitpoint
+                    .saturating_sub(config.hitpoint.bullet_damage);
+                events.push(GameEvent::Hit(Team::Mono));
+            }
+            let hits = self.collider.hit_player(
+                Team::Di,
+                &self.players.player2.get_motion(),
+                &self.hash_mono,
+                &mut self.bullets_mono,
+            );
+            for _ in 0..hits {
+                self.di_hitpoint = self
+                    .di_hitpoint
+                    .saturating_sub(config.hitpoint.bullet_damage);
+                events.push(GameEvent::Hit(Team::Di));
+            }
+            // bullet and bullet
+            self.collider.collide_bullets(
+                &self.hash_di,
+                &mut self.bullets_mono,
+                &mut self.bullets_di,
+            );
+        }
+
+        // Free bullets in outside of display, hit ones are freed at once
+        if self.clean_interval == 0 {
+            self.clean_interval = config.tick.clean_interval;
+            self.bullets_mono
+                .release_outside(&self.arena, config.hit.display_margin);
+            self.bullets_di
+                .release_outside(&self.arena, config.hit.display_margin);
+        } else {
+            self.clean_interval -= 1;
+        }
+
+        // Decide winner
+        match (self.winner, self.mono_hitpoint, self.di_hitpoint) {
+            (None, mono_hp, di_hp) if (mono_hp <= 0 || di_hp <= 0) && mono_hp > di_hp => {
+                self.winner = Some(Team::Mono);
+                events.push(GameEvent::Won(Team::Mono));
+            }
+            (None, mono_hp, di_hp) if (mono_hp <= 0 || di_hp <= 0) && di_hp > mono_hp => {
+                self.winner = Some(Team::Di);
+                events.push(GameEvent::Won(Team::Di));
+            }
+            (None, mono_hp, di_hp) if (mono_hp <= 0 || di_hp <= 0) && mono_hp == di_hp => {
+                self.mono_hitpoint = config.hitpoint.bullet_damage;
+                self.di_hitpoint = config.hitpoint.bullet_damage;
+                events.push(GameEvent::SuddenDeath);
+            }
+            _ => (),
+        }
+
+        // Exit
+        match self.tick_for_exit {
+            Some(0) => {
+                self.over = true;
+            }
+            Some(t) => {
+                self.tick_for_exit = Some(t - 1);
+            }
+            None => {
+                if self.winner.is_some() {
+                    self.tick_for_exit = Some(config.tick.result_ticks);
+                }
+            }
+        }
+
+        events
+    }
+}
+
+// Draw `state` on a cleared `display`
+pub fn render<D>(state: &GameState, display: &mut D) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let config = config();
+    let arena = state.arena;
+    let character_style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    let text_style = TextStyleBuilder::new().baseline(Baseline::Top);
+    let mono_text_style = text_style.alignment(Alignment::Left).build();
+    let di_text_style = text_style.alignment(Alignment::Right).build();
+    let hitpoint_bar_stroke = PrimitiveStyleBuilder::new()
+        .stroke_color(BinaryColor::On)
+        .stroke_width(1)
+        .build();
+
+    // clear display
+    display.clear(BinaryColor::Off)?;
+
+    // draw players
+    state.players.player1.draw(display)?;
+    state.players.player2.draw(display)?;
+
+    // draw bullets
+    for bullet in state
+        .bullets_mono
+        .slots()
+        .iter()
+        .chain(state.bullets_di.slots())
+    {
+        if bullet.active {
+            bullet.draw(display)?;
+        }
+    }
+
+    // Draw hitpoint bar
+    // full bars meet in the middle of the display
+    let bar_length =
+        |hitpoint: i8| hitpoint as i32 * (arena.width / 2) / config.hitpoint.initial as i32;
+    Line::new(
+        Point::new(0, 0),
+        Point::new(bar_length(state.mono_hitpoint), 0),
+    )
+    .into_styled(hitpoint_bar_stroke)
+    .draw(display)?;
+    Line::new(
+        Point::new(arena.width - bar_length(state.di_hitpoint), 0),
+        Point::new(arena.width, 0),
+    )
+    .into_styled(hitpoint_bar_stroke)
+    .draw(display)?;
+
+    // Draw result
+    if let Some(winner) = state.winner {
+        let (mono_result_text, di_result_text) = match winner {
+            Team::Mono => ("Win", "Lose"),
+            Team::Di => ("Lose", "Win"),
+        };
+        Text::with_text_style(
+            mono_result_text,
+            Point::zero(),
+            character_style,
+            mono_text_style,
+        )
+        .draw(display)?;
+        Text::with_text_style(
+            di_result_text,
+            Point::new(arena.width, 0),
+            character_style,
+            di_text_style,
+        )
+        .draw(display)?;
+        // the seed replays the match
+        Text::with_text_style(
+            &format!("seed {}", state.seed),
+            Point::new(arena.width, arena.height),
+            MonoTextStyle::new(&FONT_4X6, BinaryColor::On),
+            TextStyleBuilder::new()
+                .alignment(Alignment::Right)
+                .baseline(Baseline::Bottom)
+                .build(),
+        )
+        .draw(display)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Position;
+    use device_query::Keycode;
+
+    fn new_state(seed: u64) -> GameState {
+        let arena = Arena::new(config().display.size.size());
+        GameState::new(&config().key_bindings(), arena, seed)
+    }
+
+    // Players moving up and down and firing lasers now and then
+    fn inputs(tick: u32) -> Inputs {
+        let keycodes = if tick % 40 < 20 {
+            vec![Keycode::R, Keycode::M]
+        } else {
+            vec![Keycode::C, Keycode::I]
+        };
+        Inputs {
+            keycodes,
+            laser1: tick.is_multiple_of(500),
+            laser2: tick.is_multiple_of(700),
+        }
+    }
+
+    fn snapshot(state: &GameState) -> (i8, i8, Vec<Position>, Vec<Position>) {
+        let active = |bullets: &Bullets| {
+            bullets
+                .slots()
+                .iter()
+                .filter(|bullet| bullet.active)
+                .map(|bullet| bullet.get_position())
+                .collect()
+        };
+        (
+            state.mono_hitpoint,
+            state.di_hitpoint,
+            active(&state.bullets_mono),
+            active(&state.bullets_di),
+        )
+    }
+
+    #[test]
+    fn same_seed_same_match() {
+        let mut a = new_state(7);
+        let mut b = new_state(7);
+        for tick in 0..5000 {
+            let inputs = inputs(tick);
+            assert_eq!(a.step(&inputs), b.step(&inputs));
+            assert_eq!(snapshot(&a), snapshot(&b));
+            assert_eq!(
+                a.players.player1.get_position(),
+                b.players.player1.get_position()
+            );
+            assert_eq!(
+                a.players.player2.get_position(),
+                b.players.player2.get_position()
+            );
+        }
+    }
+
+    #[test]
+    fn hit_takes_bullet_damage() {
+        let damage = config().hitpoint.bullet_damage;
+        let mut state = new_state(3);
+        for tick in 0..100_000 {
+            let before = (state.mono_hitpoint, state.di_hitpoint);
+            let events = state.step(&inputs(tick));
+            let hits = |team| {
+                events
+                    .iter()
+                    .filter(|event| **event == GameEvent::Hit(team))
+                    .count() as i8
+            };
+            if hits(Team::Mono) + hits(Team::Di) > 0 {
+                assert_eq!(state.mono_hitpoint, before.0 - hits(Team::Mono) * damage);
+                assert_eq!(state.di_hitpoint, before.1 - hits(Team::Di) * damage);
+                return;
+            }
+            assert_eq!((state.mono_hitpoint, state.di_hitpoint), before);
+        }
+        panic!("no hit");
+    }
+
+    #[test]
+    fn sudden_death_on_equal_hitpoints() {
+        let damage = config().hitpoint.bullet_damage;
+        let mut state = new_state(0);
+        state.mono_hitpoint = 0;
+        state.di_hitpoint = 0;
+        let events = state.step(&Inputs::default());
+        assert!(events.contains(&GameEvent::SuddenDeath));
+        assert_eq!((state.mono_hitpoint, state.di_hitpoint), (damage, damage));
+        assert_eq!(state.winner, None);
+    }
+}
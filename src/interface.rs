@@ -1,66 +1,77 @@
-use crate::constant::{BUTTON1_PIN, BUTTON2_PIN, LED1_PIN, LED2_PIN};
-use device_query::{keymap::Keycode, DeviceQuery, DeviceState};
-use rppal::gpio::Gpio;
-use rppal::{
-    gpio::{InputPin, Level, OutputPin},
-    i2c::I2c,
-};
-use ssd1306::{mode::BufferedGraphicsMode, prelude::*, Ssd1306};
+pub mod debounce;
+pub mod evdev;
+pub mod frame_buffer;
+pub mod gif_recorder;
+pub mod headless;
+pub mod led_pattern;
+pub mod mock;
+pub mod null;
+pub mod oled;
+pub mod page_buffer;
+#[cfg(feature = "rpi")]
+pub mod rpi;
+pub mod terminal;
+pub mod terminal_keyboard;
+pub mod x11;
+
+use crate::key_binding::KeyBindings;
+use crate::sound::{Note, SoundPlayer};
+use debounce::DebouncedButtons;
+use device_query::keymap::Keycode;
+use display_interface::DisplayError;
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+use led_pattern::PatternLed;
+use std::time::Duration;
 
 // Interfaces
-pub struct Interfaces {
-    pub display: Display,
-    pub buttons: Buttons,
+pub struct Interfaces<D: Display> {
+    pub display: D,
+    pub buttons: DebouncedButtons,
     pub leds: Leds,
-    pub keyboard: Keyboard,
+    pub keyboard: Box<dyn Keyboard>,
+    pub sound: SoundPlayer,
 }
-impl Interfaces {
-    pub fn new(display: Display, buttons: Buttons, leds: Leds, keyboard: Keyboard) -> Self {
+impl<D: Display> Interfaces<D> {
+    pub fn new(
+        display: D,
+        buttons: Box<dyn Buttons>,
+        leds: Leds,
+        keyboard: Box<dyn Keyboard>,
+        buzzer: Box<dyn Buzzer>,
+    ) -> Self {
         Self {
             display,
-            buttons,
+            buttons: DebouncedButtons::new(buttons),
             leds,
             keyboard,
+            sound: SoundPlayer::new(buzzer),
         }
     }
 }
 
 // Display
-pub type Display =
-    Ssd1306<I2CInterface<I2c>, DisplaySize128x64, BufferedGraphicsMode<DisplaySize128x64>>;
+// A monochrome frame buffer which is sent to the screen on `flush`.
+pub trait Display:
+    DrawTarget<Color = BinaryColor, Error = DisplayError> + OriginDimensions
+{
+    fn flush(&mut self) -> Result<(), DisplayError>;
+}
 
 // Button
-pub struct Buttons {
-    button1: InputPin,
-    button2: InputPin,
-}
-impl Buttons {
-    pub fn new(gpio: &Gpio) -> Self {
-        let button1 = gpio.get(BUTTON1_PIN).unwrap().into_input_pullup();
-        let button2 = gpio.get(BUTTON2_PIN).unwrap().into_input_pullup();
-        Self { button1, button2 }
-    }
-    pub fn get_levels(&self) -> ButtonLevels {
-        ButtonLevels::new(self.button1.read(), self.button2.read())
-    }
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Level {
+    Low,
+    High,
 }
 
-// Leds
-pub struct Leds {
-    pub led1: OutputPin,
-    pub led2: OutputPin,
-}
-impl Leds {
-    pub fn new(gpio: &Gpio) -> Self {
-        let mut led1 = gpio.get(LED1_PIN).unwrap().into_output();
-        let mut led2 = gpio.get(LED2_PIN).unwrap().into_output();
-        led1.set_low();
-        led2.set_low();
-        Self { led1, led2 }
-    }
+// Raw levels, `Low` is pressed. Read through `DebouncedButtons`.
+pub trait Buttons {
+    fn get_levels(&mut self) -> ButtonLevels;
+    // Time of the last read since the buttons were created
+    fn elapsed(&self) -> Duration;
 }
-pub type Led = OutputPin;
 
+#[derive(Copy, Clone, Debug)]
 pub struct ButtonLevels {
     pub button1_level: Level,
     pub button2_level: Level,
@@ -74,18 +85,45 @@ impl ButtonLevels {
     }
 }
 
-// Keyboard
-pub type Keycodes = Vec<Keycode>;
-pub struct Keyboard {
-    device_state: DeviceState,
+// Leds
+pub trait Led {
+    fn set_high(&mut self);
+    fn set_low(&mut self);
+}
+
+// Player LEDs, driven by patterns once per tick
+pub struct Leds {
+    pub led1: PatternLed,
+    pub led2: PatternLed,
 }
-impl Keyboard {
-    pub fn new() -> Self {
+impl Leds {
+    pub fn new(led1: Box<dyn Led>, led2: Box<dyn Led>) -> Self {
         Self {
-            device_state: DeviceState::new(),
+            led1: PatternLed::new(led1),
+            led2: PatternLed::new(led2),
         }
     }
-    pub fn get_keycodes(&self) -> Keycodes {
-        self.device_state.get_keys()
+    pub fn tick(&mut self) {
+        self.led1.tick();
+        self.led2.tick();
+    }
+}
+
+// Buzzer
+pub trait Buzzer {
+    // Sound `note` until the next call, a rest is silent
+    fn play(&mut self, note: &Note);
+    fn stop(&mut self);
+}
+
+// Keyboard
+pub type Keycodes = Vec<Keycode>;
+pub trait Keyboard {
+    fn get_keycodes(&mut self) -> Keycodes;
+    // The players' keys changed, for keyboards which press them themselves
+    fn bind(&mut self, _key_bindings: &KeyBindings) {}
+    // Leave the game, e.g. on Ctrl-C in a raw mode terminal
+    fn quit_requested(&self) -> bool {
+        false
     }
 }
@@ -0,0 +1,245 @@
+// Debounced buttons. A level has to stay for `buttons.debounce_ms` before it
+// counts, and changes of the debounced level are reported as events with the
+// time they started.
+use super::{ButtonLevels, Buttons, Level};
+use crate::config::config;
+use std::time::Duration;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Button {
+    Button1,
+    Button2,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ButtonEventKind {
+    Pressed,
+    Released,
+    // Still pressed, reported on every poll after `Pressed`
+    Held,
+    // Pressed for `buttons.long_press_ms`, reported once per press
+    LongPress,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ButtonEvent {
+    pub button: Button,
+    pub kind: ButtonEventKind,
+    // Time since the buttons were created
+    pub at: Duration,
+}
+
+pub type ButtonEvents = Vec<ButtonEvent>;
+
+// Whether `events` has a `kind` event of `button`
+pub fn has_event(events: &ButtonEvents, button: Button, kind: ButtonEventKind) -> bool {
+    events
+        .iter()
+        .any(|event| event.button == button && event.kind == kind)
+}
+
+// Debounced state of one button
+struct Debouncer {
+    button: Button,
+    // Unknown until the first read, which is taken as it is
+    level: Option<Level>,
+    // Level which differs from `level` and since when
+    change: Option<(Level, Duration)>,
+    pressed_at: Option<Duration>,
+    long_pressed: bool,
+}
+impl Debouncer {
+    fn new(button: Button) -> Self {
+        Self {
+            button,
+            level: None,
+            change: None,
+            pressed_at: None,
+            long_pressed: false,
+        }
+    }
+
+    fn update(&mut self, raw: Level, now: Duration, events: &mut ButtonEvents) {
+        let buttons = &config().buttons;
+        let Some(level) = self.level else {
+            self.level = Some(raw);
+            if raw == Level::Low {
+                self.pressed_at = Some(now);
+            }
+            return;
+        };
+        if raw == level {
+            self.change = None;
+        } else {
+            let (_, since) = *self.change.get_or_insert((raw, now));
+            if now - since >= buttons.debounce() {
+                self.level = Some(raw);
+                self.change = None;
+                let kind = match raw {
+                    Level::Low => {
+                        self.pressed_at = Some(since);
+                        self.long_pressed = false;
+                        ButtonEventKind::Pressed
+                    }
+                    Level::High => {
+                        self.pressed_at = None;
+                        ButtonEventKind::Released
+                    }
+                };
+                events.push(self.event(kind, since));
+                return;
+            }
+        }
+        if let Some(pressed_at) = self.pressed_at {
+            events.push(self.event(ButtonEventKind::Held, now));
+            if !self.long_pressed && now - pressed_at >= buttons.long_press() {
+                self.long_pressed = true;
+                events.push(self.event(ButtonEventKind::LongPress, now));
+            }
+        }
+    }
+
+    fn event(&self, kind: ButtonEventKind, at: Duration) -> ButtonEvent {
+        ButtonEvent {
+            button: self.button,
+            kind,
+            at,
+        }
+    }
+
+    fn is_pressed(&self) -> bool {
+        self.level == Some(Level::Low)
+    }
+}
+
+pub struct DebouncedButtons {
+    buttons: Box<dyn Buttons>,
+    button1: Debouncer,
+    button2: Debouncer,
+}
+impl DebouncedButtons {
+    pub fn new(buttons: Box<dyn Buttons>) -> Self {
+        Self {
+            buttons,
+            button1: Debouncer::new(Button::Button1),
+            button2: Debouncer::new(Button::Button2),
+        }
+    }
+
+    // Read the buttons and return what happened since the last poll
+    pub fn poll(&mut self) -> ButtonEvents {
+        let ButtonLevels {
+            button1_level,
+            button2_level,
+        } = self.buttons.get_levels();
+        let now = self.buttons.elapsed();
+        let mut events = Vec::new();
+        self.button1.update(button1_level, now, &mut events);
+        self.button2.update(button2_level, now, &mut events);
+        events
+    }
+
+    // Debounced state as of the last poll
+    pub fn is_pressed(&self, button: Button) -> bool {
+        match button {
+            Button::Button1 => self.button1.is_pressed(),
+            Button::Button2 => self.button2.is_pressed(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interface::mock::{ScriptedButtons, TickCounter};
+
+    const PRESSED: ButtonLevels = ButtonLevels {
+        button1_level: Level::Low,
+        button2_level: Level::High,
+    };
+    const RELEASED: ButtonLevels = ButtonLevels {
+        button1_level: Level::High,
+        button2_level: Level::High,
+    };
+
+    fn at(tick: u64) -> Duration {
+        config().tick.tick_size() * tick as u32
+    }
+
+    // Events of button 1 on each of `polls` ticks of `script`
+    fn poll(script: Vec<(u64, ButtonLevels)>, polls: u64) -> Vec<(u64, ButtonEvent)> {
+        let ticks = TickCounter::new();
+        let mut buttons =
+            DebouncedButtons::new(Box::new(ScriptedButtons::new(ticks.clone(), script)));
+        let mut events = Vec::new();
+        for _ in 0..polls {
+            for event in buttons.poll() {
+                assert_eq!(event.button, Button::Button1);
+                events.push((ticks.get(), event));
+            }
+        }
+        events
+    }
+
+    fn kinds(events: &[(u64, ButtonEvent)], kind: ButtonEventKind) -> Vec<(u64, Duration)> {
+        events
+            .iter()
+            .filter(|(_, event)| event.kind == kind)
+            .map(|(tick, event)| (*tick, event.at))
+            .collect()
+    }
+
+    #[test]
+    fn bouncing_press() {
+        let debounce = config().tick.ticks(config().buttons.debounce());
+        // Contacts bouncing for a tick at a time, then closed
+        let script = vec![
+            (10, PRESSED),
+            (11, RELEASED),
+            (12, PRESSED),
+            (13, RELEASED),
+            (14, PRESSED),
+        ];
+        let events = poll(script, 100);
+        let pressed = kinds(&events, ButtonEventKind::Pressed);
+        assert_eq!(pressed.len(), 1, "{events:?}");
+        let (tick, pressed_at) = pressed[0];
+        assert_eq!(pressed_at, at(14));
+        assert!(tick >= 14 + debounce);
+        assert!(kinds(&events, ButtonEventKind::Released).is_empty());
+    }
+
+    #[test]
+    fn held_then_long_press_then_released() {
+        let long_press = config().buttons.long_press();
+        let release = 20 + config().tick.ticks(long_press) + 50;
+        let events = poll(vec![(20, PRESSED), (release, RELEASED)], release + 100);
+
+        let pressed = kinds(&events, ButtonEventKind::Pressed);
+        let [(pressed_tick, pressed_at)] = pressed[..] else {
+            panic!("expected one press, got {pressed:?}");
+        };
+        assert_eq!(pressed_at, at(20));
+        let released = kinds(&events, ButtonEventKind::Released);
+        let [(released_tick, released_at)] = released[..] else {
+            panic!("expected one release, got {released:?}");
+        };
+        assert_eq!(released_at, at(release));
+
+        // Held on every poll from the press until the release is debounced
+        let held = kinds(&events, ButtonEventKind::Held);
+        let held_ticks: Vec<u64> = held.iter().map(|(tick, _)| *tick).collect();
+        assert_eq!(
+            held_ticks,
+            (pressed_tick + 1..released_tick).collect::<Vec<_>>()
+        );
+        assert!(held.iter().all(|(tick, held_at)| *held_at == at(*tick)));
+
+        let long = kinds(&events, ButtonEventKind::LongPress);
+        let [(_, long_at)] = long[..] else {
+            panic!("expected one long press, got {long:?}");
+        };
+        assert!(long_at - pressed_at >= long_press);
+        assert!(long_at - pressed_at < long_press + config().tick.tick_size());
+    }
+}
@@ -0,0 +1,537 @@
+// Input backend reading Linux input devices (`/dev/input/event*`) directly,
+// which works without X11. Keyboard keys are reported as they are and the
+// d-pad of each gamepad presses the keys of one player. Captured event
+// streams (e.g. `cat /dev/input/event0 > capture`) can be replayed instead of
+// devices.
+use super::{Keyboard, Keycodes};
+use crate::config::config;
+use crate::key_binding::KeyBindings;
+use anyhow::bail;
+use device_query::Keycode;
+use std::{
+    collections::HashSet,
+    ffi::c_long,
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Read},
+    mem::size_of,
+    os::unix::fs::OpenOptionsExt,
+    path::PathBuf,
+    time::Duration,
+};
+
+// Size of `struct input_event`: timeval, type, code and value
+const EVENT_SIZE: usize = 2 * size_of::<c_long>() + 8;
+// Event types
+const EV_KEY: u16 = 0x01;
+const EV_ABS: u16 = 0x03;
+// Gamepad d-pad buttons and hat axes
+const BTN_DPAD_UP: u16 = 0x220;
+const BTN_DPAD_DOWN: u16 = 0x221;
+const BTN_DPAD_LEFT: u16 = 0x222;
+const BTN_DPAD_RIGHT: u16 = 0x223;
+const ABS_HAT0X: u16 = 0x10;
+const ABS_HAT0Y: u16 = 0x11;
+
+#[derive(Copy, Clone, Debug)]
+struct InputEvent {
+    time: Duration,
+    kind: u16,
+    code: u16,
+    value: i32,
+}
+impl InputEvent {
+    // Parse one event in the native layout
+    fn parse(bytes: &[u8]) -> Self {
+        let long = size_of::<c_long>();
+        let read_long = |offset: usize| {
+            let mut buffer = [0; size_of::<c_long>()];
+            buffer.copy_from_slice(&bytes[offset..offset + long]);
+            c_long::from_ne_bytes(buffer)
+        };
+        let (seconds, micros) = (read_long(0), read_long(long));
+        let tail = &bytes[2 * long..];
+        Self {
+            time: Duration::from_secs(seconds as u64) + Duration::from_micros(micros as u64),
+            kind: u16::from_ne_bytes([tail[0], tail[1]]),
+            code: u16::from_ne_bytes([tail[2], tail[3]]),
+            value: i32::from_ne_bytes([tail[4], tail[5], tail[6], tail[7]]),
+        }
+    }
+}
+
+// Keys pressed by the d-pad directions of a gamepad
+#[derive(Copy, Clone, Debug)]
+pub struct GamepadKeys {
+    pub up: Option<Keycode>,
+    pub down: Option<Keycode>,
+    pub left: Option<Keycode>,
+    pub right: Option<Keycode>,
+}
+
+enum Source {
+    Device(File),
+    // Captured events played at their original pace, taking each read as a
+    // tick of `tick.tick_ms` so a replay gives the same keys on the same ticks
+    // every run
+    Replay {
+        events: Vec<InputEvent>,
+        // Time of the first event of all the captures replayed together, so
+        // the devices stay in step
+        start: Duration,
+        next: usize,
+        reads: u32,
+    },
+}
+
+struct Device {
+    source: Source,
+    // Bytes of an event which is not read completely yet
+    pending: Vec<u8>,
+    keys: HashSet<Keycode>,
+    // Index into the gamepad keys, given on the first d-pad event
+    gamepad: Option<usize>,
+    // Pressed up, down, left and right by buttons and by the hat
+    dpad: [bool; 4],
+    hat: [bool; 4],
+    closed: bool,
+}
+impl Device {
+    fn new(source: Source) -> Self {
+        Self {
+            source,
+            pending: Vec::new(),
+            keys: HashSet::new(),
+            gamepad: None,
+            dpad: [false; 4],
+            hat: [false; 4],
+            closed: false,
+        }
+    }
+    fn read_events(&mut self) -> Vec<InputEvent> {
+        match &mut self.source {
+            Source::Device(file) => {
+                let mut buffer = [0; 64 * EVENT_SIZE];
+                loop {
+                    match file.read(&mut buffer) {
+                        Ok(0) => break,
+                        Ok(n) => self.pending.extend_from_slice(&buffer[..n]),
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                        Err(_) => {
+                            // Unplugged
+                            self.closed = true;
+                            break;
+                        }
+                    }
+                }
+                let complete = self.pending.len() / EVENT_SIZE * EVENT_SIZE;
+                let events = self.pending[..complete]
+                    .chunks_exact(EVENT_SIZE)
+                    .map(InputEvent::parse)
+                    .collect();
+                self.pending.drain(..complete);
+                events
+            }
+            Source::Replay {
+                events,
+                start,
+                next,
+                reads,
+            } => {
+                let elapsed = config().tick.tick_size() * *reads;
+                *reads += 1;
+                let from = *next;
+                // Saturating, the clock of the capture may have been set back
+                while *next < events.len() && events[*next].time.saturating_sub(*start) <= elapsed {
+                    *next += 1;
+                }
+                events[from..*next].to_vec()
+            }
+        }
+    }
+    fn handle(&mut self, event: InputEvent, gamepads: &mut usize, slots: usize) {
+        let direction = match (event.kind, event.code) {
+            (EV_KEY, BTN_DPAD_UP) => Some(0),
+            (EV_KEY, BTN_DPAD_DOWN) => Some(1),
+            (EV_KEY, BTN_DPAD_LEFT) => Some(2),
+            (EV_KEY, BTN_DPAD_RIGHT) => Some(3),
+            _ => None,
+        };
+        match (event.kind, event.code, direction) {
+            (EV_KEY, _, Some(direction)) => self.dpad[direction] = event.value != 0,
+            (EV_KEY, code, None) => {
+                if let Some(keycode) = to_keycode(code) {
+                    // 1 is press, 2 is auto repeat and 0 is release
+                    if event.value == 0 {
+                        self.keys.remove(&keycode);
+                    } else {
+                        self.keys.insert(keycode);
+                    }
+                }
+                return;
+            }
+            (EV_ABS, ABS_HAT0X, _) => {
+                self.hat[2] = event.value < 0;
+                self.hat[3] = event.value > 0;
+            }
+            (EV_ABS, ABS_HAT0Y, _) => {
+                self.hat[0] = event.value < 0;
+                self.hat[1] = event.value > 0;
+            }
+            _ => return,
+        }
+        if self.gamepad.is_none() && *gamepads < slots {
+            self.gamepad = Some(*gamepads);
+            *gamepads += 1;
+        }
+    }
+}
+
+pub struct EvdevKeyboard {
+    devices: Vec<Device>,
+    gamepad_keys: Vec<GamepadKeys>,
+    // Number of devices which became a gamepad
+    gamepads: usize,
+}
+impl EvdevKeyboard {
+    // Read every input device which can be opened
+    pub fn open(gamepad_keys: Vec<GamepadKeys>) -> anyhow::Result<Self> {
+        let mut paths: Vec<PathBuf> = fs::read_dir("/dev/input")?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("event"))
+            })
+            .collect();
+        paths.sort();
+        let devices: Vec<Device> = paths
+            .iter()
+            .filter_map(|path| {
+                OpenOptions::new()
+                    .read(true)
+                    .custom_flags(libc::O_NONBLOCK)
+                    .open(path)
+                    .ok()
+            })
+            .map(|file| Device::new(Source::Device(file)))
+            .collect();
+        if devices.is_empty() {
+            bail!("no readable input device (is the user in the `input` group?)");
+        }
+        Ok(Self::new(devices, gamepad_keys))
+    }
+    // Replay captured event streams, one file per device
+    pub fn replay(paths: &[PathBuf], gamepad_keys: Vec<GamepadKeys>) -> anyhow::Result<Self> {
+        let mut captures = Vec::new();
+        for path in paths {
+            let bytes = fs::read(path)?;
+            if bytes.len() % EVENT_SIZE != 0 {
+                bail!(
+                    "{} is not a stream of {}-byte input events",
+                    path.display(),
+                    EVENT_SIZE
+                );
+            }
+            let events: Vec<InputEvent> = bytes
+                .chunks_exact(EVENT_SIZE)
+                .map(InputEvent::parse)
+                .collect();
+            captures.push(events);
+        }
+        let start = captures
+            .iter()
+            .filter_map(|events| events.first())
+            .map(|event| event.time)
+            .min()
+            .unwrap_or_default();
+        let devices = captures
+            .into_iter()
+            .map(|events| {
+                Device::new(Source::Replay {
+                    events,
+                    start,
+                    next: 0,
+                    reads: 0,
+                })
+            })
+            .collect();
+        Ok(Self::new(devices, gamepad_keys))
+    }
+    fn new(devices: Vec<Device>, gamepad_keys: Vec<GamepadKeys>) -> Self {
+        Self {
+            devices,
+            gamepad_keys,
+            gamepads: 0,
+        }
+    }
+}
+impl Keyboard for EvdevKeyboard {
+    fn get_keycodes(&mut self) -> Keycodes {
+        let slots = self.gamepad_keys.len();
+        let mut keycodes = HashSet::new();
+        for device in &mut self.devices {
+            for event in device.read_events() {
+                device.handle(event, &mut self.gamepads, slots);
+            }
+            keycodes.extend(device.keys.iter().copied());
+            if let Some(gamepad) = device.gamepad {
+                let keys = &self.gamepad_keys[gamepad];
+                let directions = [keys.up, keys.down, keys.left, keys.right];
+                for (i, keycode) in directions.into_iter().enumerate() {
+                    if device.dpad[i] || device.hat[i] {
+                        keycodes.extend(keycode);
+                    }
+                }
+            }
+        }
+        self.devices.retain(|device| !device.closed);
+        keycodes.into_iter().collect()
+    }
+    fn bind(&mut self, key_bindings: &KeyBindings) {
+        self.gamepad_keys = key_bindings.gamepad_keys();
+    }
+}
+
+// Linux key codes from `input-event-codes.h`
+fn to_keycode(code: u16) -> Option<Keycode> {
+    let keycode = match code {
+        1 => Keycode::Escape,
+        2 => Keycode::Key1,
+        3 => Keycode::Key2,
+        4 => Keycode::Key3,
+        5 => Keycode::Key4,
+        6 => Keycode::Key5,
+        7 => Keycode::Key6,
+        8 => Keycode::Key7,
+        9 => Keycode::Key8,
+        10 => Keycode::Key9,
+        11 => Keycode::Key0,
+        12 => Keycode::Minus,
+        13 => Keycode::Equal,
+        14 => Keycode::Backspace,
+        15 => Keycode::Tab,
+        16 => Keycode::Q,
+        17 => Keycode::W,
+        18 => Keycode::E,
+        19 => Keycode::R,
+        20 => Keycode::T,
+        21 => Keycode::Y,
+        22 => Keycode::U,
+        23 => Keycode::I,
+        24 => Keycode::O,
+        25 => Keycode::P,
+        26 => Keycode::LeftBracket,
+        27 => Keycode::RightBracket,
+        28 => Keycode::Enter,
+        29 => Keycode::LControl,
+        30 => Keycode::A,
+        31 => Keycode::S,
+        32 => Keycode::D,
+        33 => Keycode::F,
+        34 => Keycode::G,
+        35 => Keycode::H,
+        36 => Keycode::J,
+        37 => Keycode::K,
+        38 => Keycode::L,
+        39 => Keycode::Semicolon,
+        40 => Keycode::Apostrophe,
+        41 => Keycode::Grave,
+        42 => Keycode::LShift,
+        43 => Keycode::BackSlash,
+        44 => Keycode::Z,
+        45 => Keycode::X,
+        46 => Keycode::C,
+        47 => Keycode::V,
+        48 => Keycode::B,
+        49 => Keycode::N,
+        50 => Keycode::M,
+        51 => Keycode::Comma,
+        52 => Keycode::Dot,
+        53 => Keycode::Slash,
+        54 => Keycode::RShift,
+        55 => Keycode::NumpadMultiply,
+        56 => Keycode::LAlt,
+        57 => Keycode::Space,
+        58 => Keycode::CapsLock,
+        59 => Keycode::F1,
+        60 => Keycode::F2,
+        61 => Keycode::F3,
+        62 => Keycode::F4,
+        63 => Keycode::F5,
+        64 => Keycode::F6,
+        65 => Keycode::F7,
+        66 => Keycode::F8,
+        67 => Keycode::F9,
+        68 => Keycode::F10,
+        71 => Keycode::Numpad7,
+        72 => Keycode::Numpad8,
+        73 => Keycode::Numpad9,
+        74 => Keycode::NumpadSubtract,
+        75 => Keycode::Numpad4,
+        76 => Keycode::Numpad5,
+        77 => Keycode::Numpad6,
+        78 => Keycode::NumpadAdd,
+        79 => Keycode::Numpad1,
+        80 => Keycode::Numpad2,
+        81 => Keycode::Numpad3,
+        82 => Keycode::Numpad0,
+        83 => Keycode::NumpadDecimal,
+        87 => Keycode::F11,
+        88 => Keycode::F12,
+        96 => Keycode::NumpadEnter,
+        97 => Keycode::RControl,
+        98 => Keycode::NumpadDivide,
+        100 => Keycode::RAlt,
+        102 => Keycode::Home,
+        103 => Keycode::Up,
+        104 => Keycode::PageUp,
+        105 => Keycode::Left,
+        106 => Keycode::Right,
+        107 => Keycode::End,
+        108 => Keycode::Down,
+        109 => Keycode::PageDown,
+        110 => Keycode::Insert,
+        111 => Keycode::Delete,
+        117 => Keycode::NumpadEquals,
+        125 => Keycode::LMeta,
+        126 => Keycode::RMeta,
+        _ => return None,
+    };
+    Some(keycode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_binding::Layout;
+    use std::{
+        env, process,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    const EV_SYN: u16 = 0x00;
+    const KEY_R: u16 = 19;
+    const KEY_UP: u16 = 103;
+
+    // Event written `tick` ticks into the capture, in the layout of the kernel
+    fn event(tick: u32, kind: u16, code: u16, value: i32) -> Vec<u8> {
+        let time = Duration::from_secs(1_700_000_000) + config().tick.tick_size() * tick;
+        let mut bytes = Vec::new();
+        bytes.extend((time.as_secs() as c_long).to_ne_bytes());
+        bytes.extend((time.subsec_micros() as c_long).to_ne_bytes());
+        bytes.extend(kind.to_ne_bytes());
+        bytes.extend(code.to_ne_bytes());
+        bytes.extend(value.to_ne_bytes());
+        bytes
+    }
+
+    // Keyboard replaying `captures`, with the d-pad of the first gamepad on
+    // FDRC
+    fn open(captures: &[Vec<Vec<u8>>]) -> EvdevKeyboard {
+        // Tests run in parallel, each needs its own files
+        static OPENED: AtomicUsize = AtomicUsize::new(0);
+        let run = OPENED.fetch_add(1, Ordering::Relaxed);
+        let paths: Vec<PathBuf> = (0..captures.len())
+            .map(|i| {
+                env::temp_dir().join(format!("vshooter-test-{}-{run}-{i}.evdev", process::id()))
+            })
+            .collect();
+        for (path, events) in paths.iter().zip(captures) {
+            fs::write(path, events.concat()).unwrap();
+        }
+        let gamepad_keys = vec![Layout::Fdrc.keys().gamepad_keys()];
+        let keyboard = EvdevKeyboard::replay(&paths, gamepad_keys);
+        for path in &paths {
+            fs::remove_file(path).unwrap();
+        }
+        keyboard.unwrap()
+    }
+
+    fn read(keyboard: &mut EvdevKeyboard) -> Vec<Keycode> {
+        let mut keycodes = keyboard.get_keycodes();
+        keycodes.sort_by_key(|keycode| keycode.to_string());
+        keycodes
+    }
+
+    // Keys read on each tick from a keyboard replaying `captures`
+    fn replay(captures: &[Vec<Vec<u8>>], ticks: usize) -> Vec<Vec<Keycode>> {
+        let mut keyboard = open(captures);
+        (0..ticks).map(|_| read(&mut keyboard)).collect()
+    }
+
+    #[test]
+    fn keys_and_dpad_on_their_ticks() {
+        let keyboard = vec![
+            event(0, EV_KEY, KEY_UP, 1),
+            event(0, EV_SYN, 0, 0),
+            // auto repeat
+            event(2, EV_KEY, KEY_UP, 2),
+            event(3, EV_KEY, KEY_UP, 0),
+            event(3, EV_SYN, 0, 0),
+        ];
+        let gamepad = vec![
+            event(1, EV_KEY, BTN_DPAD_DOWN, 1),
+            event(2, EV_KEY, BTN_DPAD_DOWN, 0),
+            event(2, EV_ABS, ABS_HAT0X, -1),
+            event(4, EV_ABS, ABS_HAT0X, 1),
+            event(4, EV_ABS, ABS_HAT0Y, -1),
+            event(5, EV_ABS, ABS_HAT0X, 0),
+            event(5, EV_ABS, ABS_HAT0Y, 0),
+        ];
+        assert_eq!(
+            replay(&[keyboard, gamepad], 7),
+            [
+                vec![Keycode::Up],
+                vec![Keycode::C, Keycode::Up],
+                vec![Keycode::D, Keycode::Up],
+                vec![Keycode::D],
+                vec![Keycode::F, Keycode::R],
+                vec![],
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn clock_set_back_during_capture() {
+        let events = vec![
+            event(10, EV_KEY, KEY_R, 1),
+            // the clock was set back, the event comes with the first
+            event(0, EV_KEY, KEY_R, 0),
+            event(12, EV_KEY, KEY_R, 1),
+        ];
+        assert_eq!(replay(&[events], 3), [vec![], vec![], vec![Keycode::R]]);
+    }
+
+    #[test]
+    fn captures_starting_apart_stay_in_step() {
+        // The gamepad capture starts 3 ticks after the keyboard one
+        let keyboard = vec![event(0, EV_KEY, KEY_UP, 1), event(4, EV_KEY, KEY_UP, 0)];
+        let gamepad = vec![
+            event(3, EV_KEY, BTN_DPAD_LEFT, 1),
+            event(5, EV_KEY, BTN_DPAD_LEFT, 0),
+        ];
+        assert_eq!(
+            replay(&[gamepad, keyboard], 6),
+            [
+                vec![Keycode::Up],
+                vec![Keycode::Up],
+                vec![Keycode::Up],
+                vec![Keycode::D, Keycode::Up],
+                vec![Keycode::D],
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn dpad_follows_rebound_keys() {
+        let gamepad = vec![event(0, EV_KEY, BTN_DPAD_UP, 1)];
+        let mut keyboard = open(&[gamepad]);
+        assert_eq!(read(&mut keyboard), [Keycode::R]);
+        let mut key_bindings = config().key_bindings();
+        key_bindings.player1 = Layout::Arrows.keys();
+        keyboard.bind(&key_bindings);
+        assert_eq!(read(&mut keyboard), [Keycode::Up]);
+    }
+}
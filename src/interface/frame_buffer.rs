@@ -0,0 +1,135 @@
+// In-memory monochrome frame buffer shared by the software display backends.
+use display_interface::DisplayError;
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+use std::io::Write;
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct FrameBuffer {
+    size: Size,
+    pixels: Vec<bool>,
+}
+impl FrameBuffer {
+    pub fn new(size: Size) -> Self {
+        let pixels = vec![false; (size.width * size.height) as usize];
+        Self { size, pixels }
+    }
+    pub fn get_pixel(&self, point: Point) -> bool {
+        self.index(point).is_some_and(|i| self.pixels[i])
+    }
+    // Pixels in row-major order, `true` is lit
+    pub fn pixels(&self) -> &[bool] {
+        &self.pixels
+    }
+    // Rows packed into bytes, most significant bit first
+    fn packed_rows(&self, on_bit: bool) -> Vec<u8> {
+        let row_bytes = self.size.width.div_ceil(8) as usize;
+        let mut data = vec![0; row_bytes * self.size.height as usize];
+        for y in 0..self.size.height as i32 {
+            for x in 0..self.size.width as i32 {
+                if self.get_pixel(Point::new(x, y)) == on_bit {
+                    data[y as usize * row_bytes + x as usize / 8] |= 0x80 >> (x % 8);
+                }
+            }
+        }
+        data
+    }
+    // Binary PBM (P4). Lit pixels are white like on the OLED.
+    pub fn write_pbm<W: Write>(&self, mut writer: W) -> anyhow::Result<()> {
+        write!(writer, "P4\n{} {}\n", self.size.width, self.size.height)?;
+        writer.write_all(&self.packed_rows(false))?;
+        Ok(())
+    }
+    // 1-bit grayscale PNG. Lit pixels are white like on the OLED.
+    pub fn write_png<W: Write>(&self, writer: W) -> anyhow::Result<()> {
+        let mut encoder = png::Encoder::new(writer, self.size.width, self.size.height);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::One);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.packed_rows(true))?;
+        writer.finish()?;
+        Ok(())
+    }
+    fn index(&self, point: Point) -> Option<usize> {
+        let (x, y) = (point.x, point.y);
+        if 0 <= x && x < self.size.width as i32 && 0 <= y && y < self.size.height as i32 {
+            Some((y as u32 * self.size.width + x as u32) as usize)
+        } else {
+            None
+        }
+    }
+}
+
+impl OriginDimensions for FrameBuffer {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+impl DrawTarget for FrameBuffer {
+    type Color = BinaryColor;
+    type Error = DisplayError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if let Some(i) = self.index(point) {
+                self.pixels[i] = color.is_on();
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.pixels.fill(color.is_on());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // 10x2 with the corners of the first row and the second pixel of the
+    // second lit, so a row takes more than a byte
+    fn frame() -> FrameBuffer {
+        let mut frame = FrameBuffer::new(Size::new(10, 2));
+        let lit = [Point::new(0, 0), Point::new(9, 0), Point::new(1, 1)];
+        frame
+            .draw_iter(lit.map(|point| Pixel(point, BinaryColor::On)))
+            .unwrap();
+        frame
+    }
+
+    #[test]
+    fn pbm() {
+        let mut bytes = Vec::new();
+        frame().write_pbm(&mut bytes).unwrap();
+        // Unlit pixels are the black bits, the rows padded with zeros
+        let mut golden = b"P4\n10 2\n".to_vec();
+        golden.extend([0b0111_1111, 0b1000_0000, 0b1011_1111, 0b1100_0000]);
+        assert_eq!(bytes, golden);
+    }
+
+    #[test]
+    fn png() {
+        let mut bytes = Vec::new();
+        frame().write_png(&mut bytes).unwrap();
+        let golden_header = [
+            // signature
+            0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a,
+            // IHDR: 10x2, 1 bit grayscale, deflate, the only filter method,
+            // no interlace, then the CRC
+            0, 0, 0, 13, b'I', b'H', b'D', b'R', 0, 0, 0, 10, 0, 0, 0, 2, 1, 0, 0, 0, 0, 0x49, 0x1a,
+            0x70, 0x7d,
+        ];
+        assert_eq!(bytes[..golden_header.len()], golden_header);
+        let mut reader = png::Decoder::new(Cursor::new(bytes)).read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size().unwrap()];
+        reader.next_frame(&mut data).unwrap();
+        // Lit pixels are the white bits
+        assert_eq!(data, [0b1000_0000, 0b0100_0000, 0b0100_0000, 0b0000_0000]);
+    }
+}
@@ -0,0 +1,206 @@
+// Display wrapper which records flushed frames into an animated GIF.
+use super::{frame_buffer::FrameBuffer, Display};
+use crate::config::config;
+use display_interface::DisplayError;
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+use gif::{Encoder, Frame, Repeat};
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// Dark and lit pixel
+const PALETTE: [u8; 6] = [0, 0, 0, 255, 255, 255];
+// Viewers play shorter GIF frame delays slower, so frames are merged up to it
+const MIN_DELAY_CS: u64 = 2;
+
+struct Recording {
+    encoder: Encoder<BufWriter<File>>,
+    // Last flushed frame which is not written yet
+    pending: Option<FrameBuffer>,
+    // Frames are timed by the ticks since the start, as the game loop skips
+    // frames when it is behind
+    ticks: u32,
+    elapsed: Duration,
+    written_cs: u64,
+}
+impl Recording {
+    fn new(path: &Path, size: Size) -> anyhow::Result<Self> {
+        let writer = BufWriter::new(File::create(path)?);
+        let mut encoder = Encoder::new(writer, size.width as u16, size.height as u16, &PALETTE)?;
+        encoder.set_repeat(Repeat::Infinite)?;
+        Ok(Self {
+            encoder,
+            pending: None,
+            ticks: 0,
+            elapsed: Duration::ZERO,
+            written_cs: 0,
+        })
+    }
+    fn push(&mut self, frame: &FrameBuffer) -> anyhow::Result<()> {
+        self.elapsed = config().tick.tick_size() * self.ticks;
+        match &self.pending {
+            Some(pending) if pending == frame => (),
+            _ => {
+                self.write_pending(false)?;
+                self.pending = Some(frame.clone());
+            }
+        }
+        Ok(())
+    }
+    // Write the pending frame shown until now. Frames too short for a GIF delay
+    // are dropped and their time is given to the next frame.
+    fn write_pending(&mut self, last: bool) -> anyhow::Result<()> {
+        let Some(pending) = &self.pending else {
+            return Ok(());
+        };
+        let until_cs = (self.elapsed.as_millis() as u64 + 5) / 10;
+        let delay = until_cs.saturating_sub(self.written_cs);
+        if delay < MIN_DELAY_CS && !last {
+            return Ok(());
+        }
+        let delay = delay.max(MIN_DELAY_CS);
+        let size = pending.size();
+        let indexed: Vec<u8> = pending.pixels().iter().map(|on| *on as u8).collect();
+        let mut gif_frame =
+            Frame::from_indexed_pixels(size.width as u16, size.height as u16, indexed, None);
+        gif_frame.delay = delay as u16;
+        self.encoder.write_frame(&gif_frame)?;
+        self.written_cs += delay;
+        Ok(())
+    }
+    fn finish(mut self) -> anyhow::Result<()> {
+        self.elapsed = config().tick.tick_size() * self.ticks;
+        self.write_pending(true)?;
+        self.encoder.into_inner()?;
+        Ok(())
+    }
+}
+
+pub struct GifRecorder<D: Display> {
+    display: D,
+    // Where recordings are saved, recording is disabled without it
+    directory: Option<PathBuf>,
+    frame: FrameBuffer,
+    recording: Option<Recording>,
+}
+impl<D: Display> GifRecorder<D> {
+    pub fn new(display: D, directory: Option<PathBuf>) -> Self {
+        let frame = FrameBuffer::new(display.size());
+        Self {
+            display,
+            directory,
+            frame,
+            recording: None,
+        }
+    }
+    // Start recording into a new file named after the current time
+    pub fn start(&mut self) -> anyhow::Result<()> {
+        let Some(directory) = &self.directory else {
+            return Ok(());
+        };
+        let name = chrono::Local::now().format("match-%Y%m%d-%H%M%S.gif");
+        let path = directory.join(name.to_string());
+        self.recording = Some(Recording::new(&path, self.frame.size())?);
+        Ok(())
+    }
+    // Count a game tick, called once per tick whether a frame is flushed or not
+    pub fn tick(&mut self) {
+        if let Some(recording) = &mut self.recording {
+            recording.ticks += 1;
+        }
+    }
+    pub fn finish(&mut self) -> anyhow::Result<()> {
+        match self.recording.take() {
+            Some(recording) => recording.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<D: Display> OriginDimensions for GifRecorder<D> {
+    fn size(&self) -> Size {
+        self.display.size()
+    }
+}
+
+impl<D: Display> DrawTarget for GifRecorder<D> {
+    type Color = BinaryColor;
+    type Error = DisplayError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        if self.recording.is_some() {
+            let pixels: Vec<_> = pixels.into_iter().collect();
+            self.frame.draw_iter(pixels.iter().copied())?;
+            self.display.draw_iter(pixels)
+        } else {
+            self.display.draw_iter(pixels)
+        }
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.frame.clear(color)?;
+        self.display.clear(color)
+    }
+}
+
+impl<D: Display> Display for GifRecorder<D> {
+    fn flush(&mut self) -> Result<(), DisplayError> {
+        if let Some(recording) = &mut self.recording {
+            recording
+                .push(&self.frame)
+                .map_err(|_| DisplayError::BusWriteError)?;
+        }
+        self.display.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interface::headless::HeadlessDisplay;
+    use std::{env, fs, process};
+
+    #[test]
+    fn delays_from_ticks() {
+        let directory = env::temp_dir().join(format!("vshooter-test-{}-gif", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let display = HeadlessDisplay::new(Size::new(8, 8), None);
+        let mut recorder = GifRecorder::new(display, Some(directory.clone()));
+        recorder.start().unwrap();
+        // A new frame every 10 ticks, however long the ticks took
+        for frame in 0..4 {
+            for _ in 0..10 {
+                recorder.tick();
+            }
+            Pixel(Point::new(frame, 0), BinaryColor::On)
+                .draw(&mut recorder)
+                .unwrap();
+            recorder.flush().unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let path = fs::read_dir(&directory)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let file = File::open(path).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        let mut decoder = gif::DecodeOptions::new().read_info(file).unwrap();
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay as u64);
+        }
+        let cs = |ticks: u32| (config().tick.tick_size() * ticks).as_millis() as u64 / 10;
+        // The first frame is shown from the start, the last one as briefly as
+        // a GIF allows
+        assert_eq!(delays, [cs(20), cs(10), cs(10), MIN_DELAY_CS]);
+    }
+}
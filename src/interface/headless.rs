@@ -0,0 +1,110 @@
+// Headless display backend: keeps frames in memory and optionally saves them
+// as image files.
+use super::{frame_buffer::FrameBuffer, Display};
+use display_interface::DisplayError;
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ImageFormat {
+    Pbm,
+    Png,
+}
+impl ImageFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Pbm => "pbm",
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+// Which flushed frames are saved and where
+pub struct FrameDump {
+    directory: PathBuf,
+    every: u64,
+    formats: Vec<ImageFormat>,
+}
+impl FrameDump {
+    pub fn new(directory: PathBuf, every: u64, formats: Vec<ImageFormat>) -> Self {
+        Self {
+            directory,
+            every: every.max(1),
+            formats,
+        }
+    }
+}
+
+pub struct HeadlessDisplay {
+    frame: FrameBuffer,
+    dump: Option<FrameDump>,
+    frame_count: u64,
+}
+impl HeadlessDisplay {
+    pub fn new(size: Size, dump: Option<FrameDump>) -> Self {
+        Self {
+            frame: FrameBuffer::new(size),
+            dump,
+            frame_count: 0,
+        }
+    }
+    // Save the current frame, the format is chosen by the file extension
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("pbm") => self.frame.write_pbm(writer),
+            Some("png") => self.frame.write_png(writer),
+            _ => anyhow::bail!("unknown image format: {}", path.display()),
+        }
+    }
+}
+
+impl OriginDimensions for HeadlessDisplay {
+    fn size(&self) -> Size {
+        self.frame.size()
+    }
+}
+
+impl DrawTarget for HeadlessDisplay {
+    type Color = BinaryColor;
+    type Error = DisplayError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.frame.draw_iter(pixels)
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.frame.clear(color)
+    }
+}
+
+impl Display for HeadlessDisplay {
+    fn flush(&mut self) -> Result<(), DisplayError> {
+        let frame_count = self.frame_count;
+        self.frame_count += 1;
+        if let Some(dump) = &self.dump {
+            if frame_count.is_multiple_of(dump.every) {
+                for format in &dump.formats {
+                    let path = dump.directory.join(format!(
+                        "frame-{:06}.{}",
+                        frame_count,
+                        format.extension()
+                    ));
+                    // `DisplayError` cannot carry why the frame was not saved
+                    self.save(&path).map_err(|e| {
+                        eprintln!("failed to save {}: {e:#}", path.display());
+                        DisplayError::BusWriteError
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
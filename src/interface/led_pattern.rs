@@ -0,0 +1,192 @@
+// LED animations. A pattern is set once and `PatternLed::tick` works out the
+// level for every tick, so callers only say what to show.
+use super::{Led, Level};
+use crate::config::config;
+use std::time::Duration;
+
+// Software PWM cycle of `Breathe`, short enough not to flicker
+const PWM_PERIOD: Duration = Duration::from_millis(10);
+
+// Ticks per PWM cycle, which are also the steps of brightness
+fn pwm_steps() -> u64 {
+    config().tick.ticks(PWM_PERIOD)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    Off,
+    On,
+    // On and off for `half_period` ticks each
+    Blink { half_period: u64 },
+    // Two short flashes every `period` ticks
+    DoubleBlink { period: u64 },
+    // Brightness ramps up and down over `period` ticks
+    Breathe { period: u64 },
+    // `beats` flashes over `ticks` ticks, then off
+    Countdown { ticks: u64, beats: u64 },
+}
+impl Pattern {
+    // Level `elapsed` ticks after the pattern was set
+    fn level(&self, elapsed: u64) -> Level {
+        let on = match *self {
+            Pattern::Off => false,
+            Pattern::On => true,
+            Pattern::Blink { half_period } => (elapsed / half_period.max(1)).is_multiple_of(2),
+            Pattern::DoubleBlink { period } => {
+                let flash = (period / 8).max(1);
+                let phase = elapsed % period.max(1);
+                phase < flash || (2 * flash..3 * flash).contains(&phase)
+            }
+            Pattern::Breathe { period } => {
+                let steps = pwm_steps();
+                let half = (period / 2).max(1);
+                let phase = elapsed % (2 * half);
+                // Off at the start of a period and fully on in the middle
+                let brightness = steps - half.abs_diff(phase) * steps / half;
+                elapsed % steps < brightness
+            }
+            Pattern::Countdown { ticks, beats } => {
+                let beat = (ticks / beats.max(1)).max(1);
+                elapsed < ticks && elapsed % beat < beat / 2
+            }
+        };
+        if on {
+            Level::High
+        } else {
+            Level::Low
+        }
+    }
+}
+
+pub struct PatternLed {
+    led: Box<dyn Led>,
+    pattern: Pattern,
+    // Ticks since `pattern` was set
+    elapsed: u64,
+    level: Level,
+}
+impl PatternLed {
+    pub fn new(mut led: Box<dyn Led>) -> Self {
+        led.set_low();
+        Self {
+            led,
+            pattern: Pattern::Off,
+            elapsed: 0,
+            level: Level::Low,
+        }
+    }
+
+    // Show `pattern` from the next tick, setting the running pattern again
+    // does not restart it
+    pub fn set(&mut self, pattern: Pattern) {
+        if pattern != self.pattern {
+            self.pattern = pattern;
+            self.elapsed = 0;
+        }
+    }
+
+    // Write the level of this tick, the pin is only written when it changes
+    pub fn tick(&mut self) {
+        let level = self.pattern.level(self.elapsed);
+        if level != self.level {
+            match level {
+                Level::High => self.led.set_high(),
+                Level::Low => self.led.set_low(),
+            }
+            self.level = level;
+        }
+        self.elapsed += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interface::mock::{LedWrite, RecordingLed, TickCounter};
+
+    // Levels of the first `ticks` ticks of `pattern`, `#` is on
+    fn levels(pattern: Pattern, ticks: u64) -> String {
+        (0..ticks)
+            .map(|elapsed| match pattern.level(elapsed) {
+                Level::High => '#',
+                Level::Low => '.',
+            })
+            .collect()
+    }
+
+    #[test]
+    fn blink() {
+        let pattern = Pattern::Blink { half_period: 2 };
+        assert_eq!(levels(pattern, 10), "##..##..##");
+    }
+
+    #[test]
+    fn double_blink() {
+        let pattern = Pattern::DoubleBlink { period: 16 };
+        assert_eq!(levels(pattern, 20), "##..##..........##..");
+    }
+
+    #[test]
+    fn countdown() {
+        let pattern = Pattern::Countdown {
+            ticks: 12,
+            beats: 3,
+        };
+        assert_eq!(levels(pattern, 16), "##..##..##......");
+    }
+
+    #[test]
+    fn breathe_duty_cycle() {
+        let steps = pwm_steps();
+        // Over 100 Hz at the configured tick
+        assert!(config().tick.tick_size() * steps as u32 <= PWM_PERIOD);
+        let period = 40 * steps;
+        let pattern = Pattern::Breathe { period };
+        // Ticks on in each PWM cycle of the period
+        let duty: Vec<usize> = levels(pattern, period)
+            .as_bytes()
+            .chunks(steps as usize)
+            .map(|cycle| cycle.iter().filter(|level| **level == b'#').count())
+            .collect();
+        // Off at the start, fully on in the middle, ramping up and down
+        assert_eq!(duty[0], 0);
+        assert_eq!(duty[duty.len() / 2], steps as usize);
+        let middle = duty.len() / 2;
+        assert!(duty[..=middle].windows(2).all(|w| w[0] <= w[1]), "{duty:?}");
+        assert!(duty[middle..].windows(2).all(|w| w[0] >= w[1]), "{duty:?}");
+    }
+
+    #[test]
+    fn same_pattern_does_not_restart() {
+        let ticks = TickCounter::new();
+        let recording = RecordingLed::new(ticks.clone());
+        let log = recording.log();
+        let mut led = PatternLed::new(Box::new(recording));
+        let blink = Pattern::Blink { half_period: 2 };
+        let run = |led: &mut PatternLed, pattern: Pattern, count: usize| {
+            for _ in 0..count {
+                led.set(pattern);
+                led.tick();
+                ticks.advance();
+            }
+        };
+        // Set on every tick, it keeps blinking
+        run(&mut led, blink, 6);
+        // Another pattern and back starts it over
+        run(&mut led, Pattern::Off, 1);
+        run(&mut led, blink, 3);
+        let write = |tick, level| LedWrite { tick, level };
+        assert_eq!(
+            *log.borrow(),
+            [
+                write(0, Level::Low),
+                write(0, Level::High),
+                write(2, Level::Low),
+                write(4, Level::High),
+                write(6, Level::Low),
+                write(7, Level::High),
+                write(9, Level::Low),
+            ]
+        );
+    }
+}
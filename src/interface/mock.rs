@@ -0,0 +1,314 @@
+// Test doubles for the GPIO buttons, LEDs, buzzer and the SPI display bus.
+// Buttons are read once per game tick, so reading them advances the tick
+// shared with the others.
+use super::{oled::Ssd1306Oled, ButtonLevels, Buttons, Buzzer, Led, Level};
+use crate::config::config;
+use crate::sound::Note;
+use anyhow::{bail, Context};
+use display_interface_spi::SPIInterface;
+use embedded_hal::{
+    digital::{self, OutputPin},
+    spi::{self, Operation, SpiDevice},
+};
+use ssd1306::{prelude::*, Ssd1306};
+use std::{
+    cell::{Cell, RefCell},
+    convert::Infallible,
+    rc::Rc,
+    time::Duration,
+};
+
+// Number of ticks since start
+#[derive(Clone, Default)]
+pub struct TickCounter(Rc<Cell<u64>>);
+impl TickCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn get(&self) -> u64 {
+        self.0.get()
+    }
+    pub fn advance(&self) -> u64 {
+        self.0.set(self.0.get() + 1);
+        self.0.get()
+    }
+}
+
+// Buttons
+// Levels are scripted as `(tick, levels)` pairs, each holding from its tick
+// until the next one. Both buttons are released before the first pair.
+pub struct ScriptedButtons {
+    ticks: TickCounter,
+    script: Vec<(u64, ButtonLevels)>,
+}
+impl ScriptedButtons {
+    pub fn new(ticks: TickCounter, mut script: Vec<(u64, ButtonLevels)>) -> Self {
+        script.sort_by_key(|(tick, _)| *tick);
+        Self { ticks, script }
+    }
+    // One `<tick> <button1> <button2>` per line with levels `low` or `high`,
+    // `#` starts a comment
+    pub fn parse(ticks: TickCounter, text: &str) -> anyhow::Result<Self> {
+        let mut script = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [tick, button1, button2] = fields[..] else {
+                bail!("line {}: expected `<tick> <button1> <button2>`", number + 1);
+            };
+            let tick = tick
+                .parse()
+                .with_context(|| format!("line {}: invalid tick", number + 1))?;
+            let levels = ButtonLevels::new(parse_level(button1)?, parse_level(button2)?);
+            script.push((tick, levels));
+        }
+        Ok(Self::new(ticks, script))
+    }
+}
+impl Buttons for ScriptedButtons {
+    fn get_levels(&mut self) -> ButtonLevels {
+        let tick = self.ticks.advance();
+        self.script
+            .iter()
+            .take_while(|(from, _)| *from <= tick)
+            .last()
+            .map(|(_, levels)| *levels)
+            .unwrap_or(ButtonLevels::new(Level::High, Level::High))
+    }
+    // Ticks take exactly `tick.tick_ms`, so scripted runs are repeatable
+    fn elapsed(&self) -> Duration {
+        config().tick.tick_size() * self.ticks.get() as u32
+    }
+}
+
+fn parse_level(text: &str) -> anyhow::Result<Level> {
+    match text {
+        "low" => Ok(Level::Low),
+        "high" => Ok(Level::High),
+        _ => bail!("invalid level `{text}` (expected low or high)"),
+    }
+}
+
+// Leds
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LedWrite {
+    pub tick: u64,
+    pub level: Level,
+}
+pub type LedLog = Rc<RefCell<Vec<LedWrite>>>;
+
+// Led which records every write with the tick it happened on
+pub struct RecordingLed {
+    ticks: TickCounter,
+    log: LedLog,
+}
+impl RecordingLed {
+    pub fn new(ticks: TickCounter) -> Self {
+        Self {
+            ticks,
+            log: LedLog::default(),
+        }
+    }
+    // Handle to the writes which stays valid after the led is moved into `Leds`
+    pub fn log(&self) -> LedLog {
+        self.log.clone()
+    }
+    fn record(&mut self, level: Level) {
+        let tick = self.ticks.get();
+        self.log.borrow_mut().push(LedWrite { tick, level });
+    }
+}
+impl Led for RecordingLed {
+    fn set_high(&mut self) {
+        self.record(Level::High);
+    }
+    fn set_low(&mut self) {
+        self.record(Level::Low);
+    }
+}
+
+// Buzzer
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BuzzerWrite {
+    pub tick: u64,
+    pub note: Note,
+}
+pub type BuzzerLog = Rc<RefCell<Vec<BuzzerWrite>>>;
+
+// Buzzer which records every note with the tick it started on
+pub struct RecordingBuzzer {
+    ticks: TickCounter,
+    log: BuzzerLog,
+}
+impl RecordingBuzzer {
+    pub fn new(ticks: TickCounter) -> Self {
+        Self {
+            ticks,
+            log: BuzzerLog::default(),
+        }
+    }
+    // Handle to the notes which stays valid after the buzzer is moved
+    pub fn log(&self) -> BuzzerLog {
+        self.log.clone()
+    }
+}
+impl Buzzer for RecordingBuzzer {
+    fn play(&mut self, note: &Note) {
+        let tick = self.ticks.get();
+        self.log
+            .borrow_mut()
+            .push(BuzzerWrite { tick, note: *note });
+    }
+    fn stop(&mut self) {}
+}
+
+// Spi
+// Bytes sent in one run of the data/command pin, `Level::Low` is commands
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpiWrite {
+    pub tick: u64,
+    pub dc: Level,
+    pub bytes: Vec<u8>,
+}
+pub type SpiLog = Rc<RefCell<Vec<SpiWrite>>>;
+
+// Data/command pin which the bus reads to tell commands from data
+#[derive(Clone)]
+pub struct MockDcPin(Rc<Cell<Level>>);
+impl digital::ErrorType for MockDcPin {
+    type Error = Infallible;
+}
+impl OutputPin for MockDcPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.0.set(Level::Low);
+        Ok(())
+    }
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.0.set(Level::High);
+        Ok(())
+    }
+}
+
+// Spi device which records the written bytes instead of sending them
+pub struct MockSpi {
+    ticks: TickCounter,
+    dc: MockDcPin,
+    log: SpiLog,
+}
+impl MockSpi {
+    pub fn new(ticks: TickCounter) -> Self {
+        Self {
+            ticks,
+            dc: MockDcPin(Rc::new(Cell::new(Level::Low))),
+            log: SpiLog::default(),
+        }
+    }
+    pub fn dc_pin(&self) -> MockDcPin {
+        self.dc.clone()
+    }
+    // Handle to the writes which stays valid after the bus is moved into the display
+    pub fn log(&self) -> SpiLog {
+        self.log.clone()
+    }
+    // Writes in the same tick and mode are merged, the driver sends a frame
+    // in several chunks
+    fn record(&mut self, bytes: &[u8]) {
+        let tick = self.ticks.get();
+        let dc = self.dc.0.get();
+        let mut log = self.log.borrow_mut();
+        match log.last_mut() {
+            Some(last) if last.tick == tick && last.dc == dc => last.bytes.extend(bytes),
+            _ => log.push(SpiWrite {
+                tick,
+                dc,
+                bytes: bytes.to_vec(),
+            }),
+        }
+    }
+}
+impl spi::ErrorType for MockSpi {
+    type Error = Infallible;
+}
+impl SpiDevice for MockSpi {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        for operation in operations {
+            match operation {
+                Operation::Write(bytes) => self.record(bytes),
+                Operation::Transfer(read, write) => {
+                    self.record(write);
+                    read.fill(0);
+                }
+                Operation::TransferInPlace(bytes) => {
+                    self.record(bytes);
+                    bytes.fill(0);
+                }
+                Operation::Read(bytes) => bytes.fill(0),
+                Operation::DelayNs(_) => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+pub type MockSpiInterface = SPIInterface<MockSpi, MockDcPin>;
+
+// SSD1306 driven over the mock bus, so the command stream of the real driver
+// can be checked
+pub fn new_mock_ssd1306<SIZE: DisplaySize>(
+    spi: MockSpi,
+    size: SIZE,
+) -> Ssd1306Oled<MockSpiInterface, SIZE> {
+    let dc = spi.dc_pin();
+    let spi_interface = SPIInterface::new(spi, dc);
+    let mut driver = Ssd1306::new(spi_interface, size, DisplayRotation::Rotate0);
+    driver.init().unwrap();
+    Ssd1306Oled::new(driver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+
+    #[test]
+    fn ssd1306_init_and_flush() {
+        let ticks = TickCounter::new();
+        let spi = MockSpi::new(ticks.clone());
+        let log = spi.log();
+        let mut display = new_mock_ssd1306(spi, DisplaySize128x64);
+        // The frame is sent on the next tick, apart from the init commands
+        ticks.advance();
+        Pixel(Point::new(3, 10), BinaryColor::On)
+            .draw(&mut display)
+            .unwrap();
+        crate::interface::Display::flush(&mut display).unwrap();
+
+        let log = log.borrow();
+        let (init, frame) = log.split_first().unwrap();
+        assert_eq!(init.dc, Level::Low);
+        assert_eq!(
+            init.bytes,
+            [
+                0xae, 0xd5, 0x80, 0xa8, 0x3f, 0xd3, 0x00, 0x40, 0x8d, 0x14, 0x20, 0x00, 0xda, 0x12,
+                0xa1, 0xc8, 0xd9, 0x21, 0x81, 0x5f, 0xdb, 0x40, 0xa4, 0xa6, 0x2e, 0xaf,
+            ]
+        );
+        // The first flush sends every page whole
+        assert_eq!(frame.len(), 16);
+        for (page, writes) in frame.chunks(2).enumerate() {
+            let page = page as u8;
+            assert_eq!(writes[0].dc, Level::Low);
+            assert_eq!(writes[0].bytes, [0x21, 0, 127, 0x22, page, page]);
+            assert_eq!(writes[1].dc, Level::High);
+            assert_eq!(writes[1].bytes.len(), 128);
+            let mut expected = [0; 128];
+            if page == 1 {
+                expected[3] = 1 << 2;
+            }
+            assert_eq!(writes[1].bytes, expected);
+        }
+    }
+}
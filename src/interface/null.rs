@@ -0,0 +1,26 @@
+// Stand-ins for machines without LEDs, a buzzer or a keyboard. Missing buttons
+// are `ScriptedButtons` with an empty script.
+use super::{Buzzer, Keyboard, Keycodes, Led};
+use crate::sound::Note;
+
+// Led which is not connected
+pub struct NoLed;
+impl Led for NoLed {
+    fn set_high(&mut self) {}
+    fn set_low(&mut self) {}
+}
+
+// Buzzer which is not connected
+pub struct NoBuzzer;
+impl Buzzer for NoBuzzer {
+    fn play(&mut self, _note: &Note) {}
+    fn stop(&mut self) {}
+}
+
+// Keyboard on which no key is pressed
+pub struct NoKeys;
+impl Keyboard for NoKeys {
+    fn get_keycodes(&mut self) -> Keycodes {
+        Vec::new()
+    }
+}
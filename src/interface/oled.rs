@@ -0,0 +1,174 @@
+// SSD1306 panel on any bus. The game only sees `Display`, so the bus is picked
+// by the interface `DI`. Frames are drawn into a page buffer and a flush sends
+// what changed since the last one, unless `display.flush` is full.
+use super::{page_buffer::PageBuffer, Display};
+use crate::config::{config, Flush};
+use display_interface::{DisplayError, WriteOnlyDataCommand};
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+use ssd1306::{mode::BasicMode, prelude::*, Ssd1306};
+
+pub struct Ssd1306Oled<DI, SIZE> {
+    driver: Ssd1306<DI, SIZE, BasicMode>,
+    buffer: PageBuffer,
+    flush: Flush,
+}
+impl<DI: WriteOnlyDataCommand, SIZE: DisplaySize> Ssd1306Oled<DI, SIZE> {
+    // `driver` has to be initialized
+    pub fn new(driver: Ssd1306<DI, SIZE, BasicMode>) -> Self {
+        let (width, height) = driver.dimensions();
+        Self {
+            driver,
+            buffer: PageBuffer::new(Size::new(width as u32, height as u32)),
+            flush: config().display.flush,
+        }
+    }
+}
+
+impl<DI, SIZE> OriginDimensions for Ssd1306Oled<DI, SIZE> {
+    fn size(&self) -> Size {
+        self.buffer.size()
+    }
+}
+
+impl<DI, SIZE> DrawTarget for Ssd1306Oled<DI, SIZE> {
+    type Color = BinaryColor;
+    type Error = DisplayError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.buffer.draw_iter(pixels)
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.buffer.clear(color)
+    }
+}
+
+impl<DI: WriteOnlyDataCommand, SIZE: DisplaySize> Display for Ssd1306Oled<DI, SIZE> {
+    // Each run of columns sets its draw area within the page and sends the bytes
+    fn flush(&mut self) -> Result<(), DisplayError> {
+        let driver = &mut self.driver;
+        self.buffer
+            .flush(self.flush == Flush::Full, |page, columns, bytes| {
+                let row = page as u8 * 8 + SIZE::OFFSETY;
+                driver.set_draw_area(
+                    (columns.start as u8 + SIZE::OFFSETX, row),
+                    (columns.end as u8 + SIZE::OFFSETX, row + 8),
+                )?;
+                driver.draw(bytes)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interface::mock::{
+        new_mock_ssd1306, MockSpi, MockSpiInterface, SpiLog, TickCounter,
+    };
+    use crate::interface::Level;
+    use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+    use ssd1306::size::DisplaySize128x64;
+
+    // Panel memory after the writes of `log` since the init, in horizontal
+    // addressing mode as the driver sets it up
+    fn panel_ram(log: &SpiLog) -> Vec<[u8; 128]> {
+        let mut ram = vec![[0; 128]; 8];
+        let (mut columns, mut pages) = ((0, 127), (0, 7));
+        let (mut column, mut page) = (0, 0);
+        for write in log.borrow().iter().filter(|write| write.tick > 0) {
+            match write.dc {
+                Level::Low => {
+                    for command in write.bytes.chunks(3) {
+                        match command {
+                            [0x21, start, end] => (columns, column) = ((*start, *end), *start),
+                            [0x22, start, end] => (pages, page) = ((*start, *end), *start),
+                            _ => panic!("unexpected command {command:02x?}"),
+                        }
+                    }
+                }
+                Level::High => {
+                    for byte in &write.bytes {
+                        ram[page as usize][column as usize] = *byte;
+                        if column == columns.1 {
+                            column = columns.0;
+                            page = if page == pages.1 { pages.0 } else { page + 1 };
+                        } else {
+                            column += 1;
+                        }
+                    }
+                }
+            }
+        }
+        ram
+    }
+
+    fn data_writes(log: &SpiLog, tick: u64) -> Vec<usize> {
+        log.borrow()
+            .iter()
+            .filter(|write| write.tick == tick && write.dc == Level::High)
+            .map(|write| write.bytes.len())
+            .collect()
+    }
+
+    type MockOled = Ssd1306Oled<MockSpiInterface, DisplaySize128x64>;
+
+    fn fill(display: &mut MockOled, x: i32, y: i32, size: u32, color: BinaryColor) {
+        Rectangle::new(Point::new(x, y), Size::new_equal(size))
+            .into_styled(PrimitiveStyle::with_fill(color))
+            .draw(display)
+            .unwrap();
+    }
+
+    #[test]
+    fn changed_flush_shows_the_full_frame() {
+        use BinaryColor::{Off, On};
+        let ticks = TickCounter::new();
+        let spis = [MockSpi::new(ticks.clone()), MockSpi::new(ticks.clone())];
+        let logs = spis.each_ref().map(MockSpi::log);
+        let mut displays = spis.map(|spi| new_mock_ssd1306(spi, DisplaySize128x64));
+        displays[0].flush = Flush::Changed;
+        displays[1].flush = Flush::Full;
+
+        let frames: [fn(&mut MockOled); 6] = [
+            // the first flush sends everything
+            |display| fill(display, 3, 5, 7, On),
+            // nothing changed
+            |_| (),
+            // changes in page 0 with `RUN_GAP` columns between are sent in two
+            // runs
+            |display| {
+                fill(display, 0, 0, 1, On);
+                fill(display, 11, 0, 1, On);
+            },
+            // a square across pages, the first one taken off again
+            |display| {
+                fill(display, 60, 28, 9, On);
+                fill(display, 3, 5, 7, Off);
+            },
+            // every column changes
+            |display| display.clear(On).unwrap(),
+            |display| fill(display, 120, 56, 8, Off),
+        ];
+        for (i, frame) in frames.iter().enumerate() {
+            // each frame on its own tick, apart from the init commands
+            ticks.advance();
+            for display in &mut displays {
+                frame(display);
+                display.flush().unwrap();
+            }
+            let [changed, full] = &logs;
+            assert_eq!(panel_ram(changed), panel_ram(full), "frame {i}");
+            assert_eq!(data_writes(full, ticks.get()), [128; 8]);
+            let sent = data_writes(changed, ticks.get());
+            match i {
+                0 | 4 => assert_eq!(sent, [128; 8]),
+                1 => assert_eq!(sent, []),
+                2 => assert_eq!(sent, [1, 1]),
+                _ => assert!(sent.iter().sum::<usize>() < 128 * 8),
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+// Frame buffer in the layout of the SSD1306 memory: a byte per column of each 8
+// pixel tall page, the top pixel in bit 0. It keeps a copy of what the panel
+// shows, so a flush only sends the columns of the pages which changed.
+use display_interface::DisplayError;
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+use std::ops::Range;
+
+// Unchanged columns between two changed ones which are still sent in one run.
+// A new run sets the draw area, which costs about as many bytes on the bus.
+const RUN_GAP: usize = 10;
+
+pub struct PageBuffer {
+    size: Size,
+    bytes: Vec<u8>,
+    // What the panel shows, unknown until the first flush
+    shown: Option<Vec<u8>>,
+}
+impl PageBuffer {
+    pub fn new(size: Size) -> Self {
+        let bytes = vec![0; (size.width * size.height.div_ceil(8)) as usize];
+        Self {
+            size,
+            bytes,
+            shown: None,
+        }
+    }
+
+    // Pass the runs of columns which differ from the panel to `send` with
+    // their page, or every page whole if `full`, and remember them as shown
+    pub fn flush<E>(
+        &mut self,
+        full: bool,
+        mut send: impl FnMut(u32, Range<usize>, &[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let width = self.size.width as usize;
+        for (page, bytes) in self.bytes.chunks(width).enumerate() {
+            let shown = match &self.shown {
+                Some(shown) if !full => &shown[page * width..(page + 1) * width],
+                _ => {
+                    send(page as u32, 0..width, bytes)?;
+                    continue;
+                }
+            };
+            let mut changed = (0..width).filter(|&x| bytes[x] != shown[x]);
+            let Some(mut start) = changed.next() else {
+                continue;
+            };
+            let mut end = start + 1;
+            for x in changed {
+                if x - end >= RUN_GAP {
+                    send(page as u32, start..end, &bytes[start..end])?;
+                    start = x;
+                }
+                end = x + 1;
+            }
+            send(page as u32, start..end, &bytes[start..end])?;
+        }
+        match &mut self.shown {
+            Some(shown) => shown.copy_from_slice(&self.bytes),
+            None => self.shown = Some(self.bytes.clone()),
+        }
+        Ok(())
+    }
+}
+
+impl OriginDimensions for PageBuffer {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+impl DrawTarget for PageBuffer {
+    type Color = BinaryColor;
+    type Error = DisplayError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let (width, height) = (self.size.width as i32, self.size.height as i32);
+        for Pixel(point, color) in pixels {
+            let (x, y) = (point.x, point.y);
+            if 0 <= x && x < width && 0 <= y && y < height {
+                let byte = &mut self.bytes[(y / 8 * width + x) as usize];
+                let bit = 1 << (y % 8);
+                if color.is_on() {
+                    *byte |= bit;
+                } else {
+                    *byte &= !bit;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.bytes.fill(if color.is_on() { 0xff } else { 0 });
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+// Raspberry Pi backend: SSD1306 over I2C or SPI, SH1106 over I2C, buttons and
+// LEDs on GPIO.
+use super::{oled::Ssd1306Oled, ButtonLevels, Buttons, Buzzer, Display, Led, Level};
+use crate::config::config;
+use crate::sound::Note;
+use display_interface::DisplayError;
+use display_interface_spi::SPIInterface;
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+use rppal::{
+    gpio::{self, Gpio, InputPin, OutputPin},
+    hal::Delay,
+    i2c::I2c,
+    spi::{Bus, Mode, SimpleHalSpiDevice, SlaveSelect, Spi},
+};
+use sh1106::{mode::GraphicsMode, Builder};
+use ssd1306::{prelude::*, I2CDisplayInterface, Ssd1306};
+use std::time::{Duration, Instant};
+
+// Display
+// SSD1306 drivers on the buses of the Pi, see `Ssd1306Oled`
+pub type SpiDisplayInterface = SPIInterface<SimpleHalSpiDevice<Spi>, OutputPin>;
+
+pub fn new_ssd1306<SIZE: DisplaySize>(size: SIZE) -> Ssd1306Oled<I2CInterface<I2c>, SIZE> {
+    let i2c = I2c::new().unwrap();
+    let i2c_interface = I2CDisplayInterface::new(i2c);
+    let mut driver = Ssd1306::new(i2c_interface, size, DisplayRotation::Rotate0);
+    driver.init().unwrap();
+    Ssd1306Oled::new(driver)
+}
+
+// SPI0 with CE0 as chip select, DC and RST on `config().pins`
+pub fn new_ssd1306_spi<SIZE: DisplaySize>(size: SIZE) -> Ssd1306Oled<SpiDisplayInterface, SIZE> {
+    let gpio = Gpio::new().unwrap();
+    let pins = &config().pins;
+    let spi = Spi::new(
+        Bus::Spi0,
+        SlaveSelect::Ss0,
+        config().display.spi_clock_hz,
+        Mode::Mode0,
+    )
+    .unwrap();
+    let dc = gpio.get(pins.dc).unwrap().into_output();
+    let mut rst = gpio.get(pins.rst).unwrap().into_output();
+    let spi_interface = SPIInterface::new(SimpleHalSpiDevice::new(spi), dc);
+    let mut driver = Ssd1306::new(spi_interface, size, DisplayRotation::Rotate0);
+    driver.reset(&mut rst, &mut Delay::new()).unwrap();
+    driver.init().unwrap();
+    Ssd1306Oled::new(driver)
+}
+
+pub struct Sh1106Oled(GraphicsMode<sh1106::interface::I2cInterface<I2c>>);
+
+pub fn new_sh1106(size: sh1106::displaysize::DisplaySize) -> Sh1106Oled {
+    let i2c = I2c::new().unwrap();
+    let mut display: GraphicsMode<_> = Builder::new().with_size(size).connect_i2c(i2c).into();
+    display.init().unwrap();
+    Sh1106Oled(display)
+}
+
+impl OriginDimensions for Sh1106Oled {
+    fn size(&self) -> Size {
+        self.0.size()
+    }
+}
+
+impl DrawTarget for Sh1106Oled {
+    type Color = BinaryColor;
+    type Error = DisplayError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.0.draw_iter(pixels).map_err(|e| match e {})
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        DrawTarget::clear(&mut self.0, color).map_err(|e| match e {})
+    }
+}
+
+impl Display for Sh1106Oled {
+    fn flush(&mut self) -> Result<(), DisplayError> {
+        self.0.flush().map_err(|_| DisplayError::BusWriteError)
+    }
+}
+
+// Button
+pub struct GpioButtons {
+    button1: InputPin,
+    button2: InputPin,
+    start: Instant,
+}
+impl GpioButtons {
+    pub fn new(gpio: &Gpio) -> Self {
+        let pins = &config().pins;
+        let button1 = gpio.get(pins.button1).unwrap().into_input_pullup();
+        let button2 = gpio.get(pins.button2).unwrap().into_input_pullup();
+        Self {
+            button1,
+            button2,
+            start: Instant::now(),
+        }
+    }
+}
+impl Buttons for GpioButtons {
+    fn get_levels(&mut self) -> ButtonLevels {
+        ButtonLevels::new(self.button1.read().into(), self.button2.read().into())
+    }
+    fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+impl From<gpio::Level> for Level {
+    fn from(level: gpio::Level) -> Self {
+        match level {
+            gpio::Level::Low => Level::Low,
+            gpio::Level::High => Level::High,
+        }
+    }
+}
+
+// Leds
+impl Led for OutputPin {
+    fn set_high(&mut self) {
+        OutputPin::set_high(self);
+    }
+    fn set_low(&mut self) {
+        OutputPin::set_low(self);
+    }
+}
+
+pub fn new_leds(gpio: &Gpio) -> (OutputPin, OutputPin) {
+    let pins = &config().pins;
+    let led1 = gpio.get(pins.led1).unwrap().into_output();
+    let led2 = gpio.get(pins.led2).unwrap().into_output();
+    (led1, led2)
+}
+
+// Buzzer
+// Piezo driven by software PWM at the frequency of the note. If the PWM fails
+// the buzzer stays silent for the rest of the run rather than ending the game.
+pub struct PwmBuzzer {
+    pin: OutputPin,
+    muted: bool,
+}
+impl PwmBuzzer {
+    // On `pins.buzzer`, if it is set
+    pub fn new(gpio: &Gpio) -> Option<Self> {
+        let pin = config().pins.buzzer?;
+        Some(Self {
+            pin: gpio.get(pin).unwrap().into_output_low(),
+            muted: false,
+        })
+    }
+    fn mute(&mut self, error: gpio::Error) {
+        eprintln!("buzzer muted: {error}");
+        self.muted = true;
+        let _ = self.pin.clear_pwm();
+        self.pin.set_low();
+    }
+}
+impl Buzzer for PwmBuzzer {
+    fn play(&mut self, note: &Note) {
+        match note.frequency {
+            Some(_) if self.muted => (),
+            Some(frequency) => {
+                if let Err(e) = self.pin.set_pwm_frequency(frequency as f64, 0.5) {
+                    self.mute(e);
+                }
+            }
+            None => self.stop(),
+        }
+    }
+    fn stop(&mut self) {
+        if self.muted {
+            return;
+        }
+        if let Err(e) = self.pin.clear_pwm() {
+            self.mute(e);
+        }
+        self.pin.set_low();
+    }
+}
@@ -0,0 +1,190 @@
+// Terminal display backend: draws the frame buffer with Unicode characters and
+// redraws it in place with ANSI escapes.
+use super::{frame_buffer::FrameBuffer, Display};
+use display_interface::DisplayError;
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+use std::io::{stdout, Stdout, Write};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Glyphs {
+    // 2x4 pixels per character
+    Braille,
+    // 1x2 pixels per character
+    HalfBlock,
+}
+impl Glyphs {
+    fn cell_size(&self) -> Size {
+        match self {
+            Glyphs::Braille => Size::new(2, 4),
+            Glyphs::HalfBlock => Size::new(1, 2),
+        }
+    }
+    fn glyph(&self, frame: &FrameBuffer, origin: Point) -> char {
+        match self {
+            Glyphs::Braille => {
+                // Dot numbering of the Unicode braille patterns block
+                const DOTS: [(i32, i32, u32); 8] = [
+                    (0, 0, 0x01),
+                    (0, 1, 0x02),
+                    (0, 2, 0x04),
+                    (1, 0, 0x08),
+                    (1, 1, 0x10),
+                    (1, 2, 0x20),
+                    (0, 3, 0x40),
+                    (1, 3, 0x80),
+                ];
+                let bits = DOTS
+                    .iter()
+                    .filter(|(x, y, _)| frame.get_pixel(origin + Point::new(*x, *y)))
+                    .fold(0, |bits, (_, _, bit)| bits | bit);
+                char::from_u32(0x2800 + bits).unwrap()
+            }
+            Glyphs::HalfBlock => {
+                let top = frame.get_pixel(origin);
+                let bottom = frame.get_pixel(origin + Point::new(0, 1));
+                match (top, bottom) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                }
+            }
+        }
+    }
+    // Lines of glyphs covering `frame`, ending in CR LF for raw mode
+    fn render(&self, frame: &FrameBuffer) -> String {
+        let size = frame.size();
+        let cell = self.cell_size();
+        let mut text = String::new();
+        for row in 0..size.height.div_ceil(cell.height) {
+            for column in 0..size.width.div_ceil(cell.width) {
+                let origin = Point::new((column * cell.width) as i32, (row * cell.height) as i32);
+                text.push(self.glyph(frame, origin));
+            }
+            text.push_str("\r\n");
+        }
+        text
+    }
+}
+
+pub struct TerminalDisplay {
+    frame: FrameBuffer,
+    glyphs: Glyphs,
+    stdout: Stdout,
+    started: bool,
+}
+impl TerminalDisplay {
+    pub fn new(size: Size, glyphs: Glyphs) -> Self {
+        Self {
+            frame: FrameBuffer::new(size),
+            glyphs,
+            stdout: stdout(),
+            started: false,
+        }
+    }
+}
+
+impl OriginDimensions for TerminalDisplay {
+    fn size(&self) -> Size {
+        self.frame.size()
+    }
+}
+
+impl DrawTarget for TerminalDisplay {
+    type Color = BinaryColor;
+    type Error = DisplayError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.frame.draw_iter(pixels)
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.frame.clear(color)
+    }
+}
+
+impl Display for TerminalDisplay {
+    fn flush(&mut self) -> Result<(), DisplayError> {
+        let mut text = String::new();
+        if !self.started {
+            // Clear screen and hide cursor
+            text.push_str("\x1b[2J\x1b[?25l");
+            self.started = true;
+        }
+        // Move cursor to top left
+        text.push_str("\x1b[H");
+        text.push_str(&self.glyphs.render(&self.frame));
+        let mut lock = self.stdout.lock();
+        lock.write_all(text.as_bytes())
+            .and_then(|_| lock.flush())
+            .map_err(|_| DisplayError::BusWriteError)
+    }
+}
+
+impl Drop for TerminalDisplay {
+    fn drop(&mut self) {
+        // Show cursor again
+        let _ = self.stdout.write_all(b"\x1b[?25h");
+        let _ = self.stdout.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(size: Size, lit: &[(i32, i32)]) -> FrameBuffer {
+        let mut frame = FrameBuffer::new(size);
+        frame
+            .draw_iter(
+                lit.iter()
+                    .map(|(x, y)| Pixel(Point::new(*x, *y), BinaryColor::On)),
+            )
+            .unwrap();
+        frame
+    }
+
+    #[test]
+    fn braille_dots() {
+        // Dots 1 to 8 in the order of their bits
+        let dots = [
+            (0, 0),
+            (0, 1),
+            (0, 2),
+            (1, 0),
+            (1, 1),
+            (1, 2),
+            (0, 3),
+            (1, 3),
+        ];
+        for (bit, dot) in dots.into_iter().enumerate() {
+            let text = Glyphs::Braille.render(&frame(Size::new(2, 4), &[dot]));
+            let glyph = char::from_u32(0x2800 + (1 << bit)).unwrap();
+            assert_eq!(text, format!("{glyph}\r\n"), "{dot:?}");
+        }
+    }
+
+    #[test]
+    fn braille_pattern() {
+        // A 5x5 diagonal over 3x2 cells, the cells past the frame are unlit
+        let diagonal = [(0, 0), (1, 1), (2, 2), (3, 3), (4, 4)];
+        let text = Glyphs::Braille.render(&frame(Size::new(5, 5), &diagonal));
+        // dots 1 and 5, 3 and 8, then 1
+        assert_eq!(
+            text,
+            "\u{2811}\u{2884}\u{2800}\r\n\u{2800}\u{2800}\u{2801}\r\n"
+        );
+    }
+
+    #[test]
+    fn half_blocks() {
+        let text = Glyphs::HalfBlock.render(&frame(
+            Size::new(4, 3),
+            &[(0, 0), (0, 1), (1, 0), (2, 1), (1, 2)],
+        ));
+        assert_eq!(text, "█▀▄ \r\n ▀  \r\n");
+    }
+}
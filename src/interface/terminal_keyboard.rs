@@ -0,0 +1,275 @@
+// Keyboard backend reading raw terminal input, which works over SSH and
+// without X11. Terminals usually report only presses and auto repeats, so a
+// key is treated as held until its repeats stop. Keypad keys arrive as the
+// plain digits and symbols, so they cannot be bound here. Ctrl-C asks the game
+// to quit, which leaves the running scene as if it had quit.
+use super::{Keyboard, Keycodes};
+use crate::key_binding::KeyBindings;
+use anyhow::bail;
+use crossterm::{
+    event::{
+        self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    execute,
+    terminal::{self, disable_raw_mode, enable_raw_mode},
+};
+use device_query::Keycode;
+use std::{
+    collections::HashMap,
+    io::stdout,
+    time::{Duration, Instant},
+};
+
+// How long a key is held after a press, covering the auto repeat delay
+const PRESS_HOLD: Duration = Duration::from_millis(550);
+// How long a key is held after an auto repeat
+const REPEAT_HOLD: Duration = Duration::from_millis(100);
+
+// Keys held by the events of the terminal
+struct HeldKeys {
+    // Held keys and when they are released, `None` waits for the release event
+    held: HashMap<Keycode, Option<Instant>>,
+    // Whether the terminal reports key releases
+    release_events: bool,
+}
+impl HeldKeys {
+    fn new(release_events: bool) -> Self {
+        Self {
+            held: HashMap::new(),
+            release_events,
+        }
+    }
+    fn handle(&mut self, keycode: Keycode, kind: KeyEventKind, now: Instant) {
+        match kind {
+            KeyEventKind::Release => {
+                self.held.remove(&keycode);
+            }
+            _ if self.release_events => {
+                self.held.insert(keycode, None);
+            }
+            KeyEventKind::Press | KeyEventKind::Repeat => {
+                let hold = if self.held.contains_key(&keycode) {
+                    REPEAT_HOLD
+                } else {
+                    PRESS_HOLD
+                };
+                self.held.insert(keycode, Some(now + hold));
+            }
+        }
+    }
+    // Keys still held at `now`
+    fn get(&mut self, now: Instant) -> Keycodes {
+        self.held
+            .retain(|_, until| until.is_none_or(|until| now < until));
+        self.held.keys().copied().collect()
+    }
+}
+
+pub struct TerminalKeyboard {
+    keys: HeldKeys,
+    // Ctrl-C was pressed, raw mode swallows the interrupt signal
+    interrupted: bool,
+}
+impl TerminalKeyboard {
+    pub fn new() -> anyhow::Result<Self> {
+        enable_raw_mode()?;
+        let release_events = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if release_events {
+            execute!(
+                stdout(),
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        Ok(Self {
+            keys: HeldKeys::new(release_events),
+            interrupted: false,
+        })
+    }
+    fn handle(&mut self, key: KeyEvent, now: Instant) {
+        if is_interrupt(&key) {
+            self.interrupted = true;
+        } else if let Some(keycode) = to_keycode(key.code) {
+            self.keys.handle(keycode, key.kind, now);
+        }
+    }
+    // Bindings to keypad keys would never be pressed
+    pub fn check_bindings(key_bindings: &KeyBindings) -> anyhow::Result<()> {
+        for keys in key_bindings.players() {
+            if let Some(keycode) = keys
+                .directions()
+                .into_iter()
+                .flatten()
+                .find(|k| is_keypad(**k))
+            {
+                bail!(
+                    "{keycode} is not seen by the terminal keyboard, use --keyboard x11 or evdev"
+                );
+            }
+        }
+        Ok(())
+    }
+    fn restore(&mut self) {
+        if self.keys.release_events {
+            let _ = execute!(stdout(), PopKeyboardEnhancementFlags);
+        }
+        let _ = disable_raw_mode();
+    }
+}
+impl Keyboard for TerminalKeyboard {
+    fn get_keycodes(&mut self) -> Keycodes {
+        let now = Instant::now();
+        while event::poll(Duration::ZERO).unwrap_or(false) {
+            if let Ok(Event::Key(key)) = event::read() {
+                self.handle(key, now);
+            }
+        }
+        self.keys.get(now)
+    }
+    fn quit_requested(&self) -> bool {
+        self.interrupted
+    }
+}
+impl Drop for TerminalKeyboard {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+fn is_interrupt(key: &KeyEvent) -> bool {
+    key.code == KeyCode::Char('c')
+        && key.modifiers.contains(KeyModifiers::CONTROL)
+        && key.kind != KeyEventKind::Release
+}
+
+fn to_keycode(code: KeyCode) -> Option<Keycode> {
+    let keycode = match code {
+        KeyCode::Char(c) if c.is_ascii_alphabetic() => {
+            return c.to_ascii_uppercase().to_string().parse().ok()
+        }
+        KeyCode::Char(c) if c.is_ascii_digit() => return format!("Key{c}").parse().ok(),
+        KeyCode::Char(' ') => Keycode::Space,
+        KeyCode::Char('`') => Keycode::Grave,
+        KeyCode::Char('-') => Keycode::Minus,
+        KeyCode::Char('=') => Keycode::Equal,
+        KeyCode::Char('[') => Keycode::LeftBracket,
+        KeyCode::Char(']') => Keycode::RightBracket,
+        KeyCode::Char('\\') => Keycode::BackSlash,
+        KeyCode::Char(';') => Keycode::Semicolon,
+        KeyCode::Char('\'') => Keycode::Apostrophe,
+        KeyCode::Char(',') => Keycode::Comma,
+        KeyCode::Char('.') => Keycode::Dot,
+        KeyCode::Char('/') => Keycode::Slash,
+        KeyCode::Up => Keycode::Up,
+        KeyCode::Down => Keycode::Down,
+        KeyCode::Left => Keycode::Left,
+        KeyCode::Right => Keycode::Right,
+        KeyCode::Enter => Keycode::Enter,
+        KeyCode::Esc => Keycode::Escape,
+        KeyCode::Backspace => Keycode::Backspace,
+        KeyCode::Tab => Keycode::Tab,
+        KeyCode::Home => Keycode::Home,
+        KeyCode::End => Keycode::End,
+        KeyCode::PageUp => Keycode::PageUp,
+        KeyCode::PageDown => Keycode::PageDown,
+        KeyCode::Insert => Keycode::Insert,
+        KeyCode::Delete => Keycode::Delete,
+        _ => return None,
+    };
+    Some(keycode)
+}
+
+fn is_keypad(keycode: Keycode) -> bool {
+    use Keycode::*;
+    matches!(
+        keycode,
+        Numpad0
+            | Numpad1
+            | Numpad2
+            | Numpad3
+            | Numpad4
+            | Numpad5
+            | Numpad6
+            | Numpad7
+            | Numpad8
+            | Numpad9
+            | NumpadSubtract
+            | NumpadAdd
+            | NumpadDivide
+            | NumpadMultiply
+            | NumpadEquals
+            | NumpadEnter
+            | NumpadDecimal
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyEventState;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn held_by_presses_and_repeats() {
+        let start = Instant::now();
+        let mut keys = HeldKeys::new(false);
+        keys.handle(Keycode::F, KeyEventKind::Press, start);
+        // over the auto repeat delay
+        assert_eq!(keys.get(start + ms(549)), [Keycode::F]);
+        keys.handle(Keycode::F, KeyEventKind::Press, start + ms(500));
+        keys.handle(Keycode::D, KeyEventKind::Press, start + ms(500));
+        // and between the repeats
+        assert_eq!(keys.get(start + ms(599)).len(), 2);
+        assert_eq!(keys.get(start + ms(600)), [Keycode::D]);
+        assert_eq!(keys.get(start + ms(1050)), []);
+        // Held from the press again once the repeats stopped
+        keys.handle(Keycode::F, KeyEventKind::Press, start + ms(2000));
+        assert_eq!(keys.get(start + ms(2549)), [Keycode::F]);
+        assert_eq!(keys.get(start + ms(2550)), []);
+    }
+
+    #[test]
+    fn held_until_released() {
+        let start = Instant::now();
+        let mut keys = HeldKeys::new(true);
+        keys.handle(Keycode::F, KeyEventKind::Press, start);
+        assert_eq!(keys.get(start + ms(10_000)), [Keycode::F]);
+        keys.handle(Keycode::F, KeyEventKind::Repeat, start + ms(10_000));
+        keys.handle(Keycode::F, KeyEventKind::Release, start + ms(10_001));
+        assert_eq!(keys.get(start + ms(10_001)), []);
+        // A release also ends the hold of a terminal without release events
+        let mut keys = HeldKeys::new(false);
+        keys.handle(Keycode::F, KeyEventKind::Press, start);
+        keys.handle(Keycode::F, KeyEventKind::Release, start + ms(100));
+        assert_eq!(keys.get(start + ms(100)), []);
+    }
+
+    #[test]
+    fn interrupt() {
+        let key = |code, modifiers, kind| KeyEvent {
+            code,
+            modifiers,
+            kind,
+            state: KeyEventState::NONE,
+        };
+        let c = KeyCode::Char('c');
+        assert!(is_interrupt(&key(
+            c,
+            KeyModifiers::CONTROL,
+            KeyEventKind::Press
+        )));
+        assert!(!is_interrupt(&key(
+            c,
+            KeyModifiers::CONTROL,
+            KeyEventKind::Release
+        )));
+        assert!(!is_interrupt(&key(
+            c,
+            KeyModifiers::NONE,
+            KeyEventKind::Press
+        )));
+    }
+}
@@ -0,0 +1,19 @@
+// Keyboard backend reading the X11 key state through device_query.
+use super::{Keyboard, Keycodes};
+use device_query::{DeviceQuery, DeviceState};
+
+pub struct X11Keyboard {
+    device_state: DeviceState,
+}
+impl X11Keyboard {
+    pub fn new() -> Self {
+        Self {
+            device_state: DeviceState::new(),
+        }
+    }
+}
+impl Keyboard for X11Keyboard {
+    fn get_keycodes(&mut self) -> Keycodes {
+        self.device_state.get_keys()
+    }
+}
@@ -0,0 +1,328 @@
+// Key bindings of the players. Keys are bound to directions on the screen, so
+// a layout reads the same for the player on either side.
+use crate::interface::{evdev::GamepadKeys, Keycodes};
+use anyhow::{bail, ensure, Context};
+use device_query::Keycode;
+use serde::{Deserialize, Deserializer};
+use std::{fs, path::Path};
+use toml_edit::{value, Array, DocumentMut, Item, Table};
+
+// Keys of a direction on the screen
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScreenKeys {
+    pub up: Keycodes,
+    pub down: Keycodes,
+    pub left: Keycodes,
+    pub right: Keycodes,
+}
+impl ScreenKeys {
+    pub const DIRECTION_NAMES: [&'static str; 4] = ["up", "down", "left", "right"];
+
+    pub fn directions(&self) -> [&Keycodes; 4] {
+        [&self.up, &self.down, &self.left, &self.right]
+    }
+    pub fn directions_mut(&mut self) -> [&mut Keycodes; 4] {
+        [
+            &mut self.up,
+            &mut self.down,
+            &mut self.left,
+            &mut self.right,
+        ]
+    }
+    // First key of each direction, pressed by a gamepad d-pad. Every key of a
+    // direction moves the same, so pressing one is enough. A direction left
+    // without keys in the key binding menu presses none.
+    pub fn gamepad_keys(&self) -> GamepadKeys {
+        GamepadKeys {
+            up: self.up.first().copied(),
+            down: self.down.first().copied(),
+            left: self.left.first().copied(),
+            right: self.right.first().copied(),
+        }
+    }
+}
+
+// Preset keys
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    // Player 1 of the original cabinet
+    Fdrc,
+    // Player 2 of the original cabinet
+    Jkmi,
+    Arrows,
+    Wasd,
+    // WASD on an AZERTY keyboard
+    Zqsd,
+    Ijkl,
+    Numpad,
+}
+impl Layout {
+    pub const ALL: [Layout; 7] = [
+        Layout::Fdrc,
+        Layout::Jkmi,
+        Layout::Arrows,
+        Layout::Wasd,
+        Layout::Zqsd,
+        Layout::Ijkl,
+        Layout::Numpad,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layout::Fdrc => "fdrc",
+            Layout::Jkmi => "jkmi",
+            Layout::Arrows => "arrows",
+            Layout::Wasd => "wasd",
+            Layout::Zqsd => "zqsd",
+            Layout::Ijkl => "ijkl",
+            Layout::Numpad => "numpad",
+        }
+    }
+    pub fn keys(&self) -> ScreenKeys {
+        use Keycode::*;
+        let [up, down, left, right] = match self {
+            Layout::Fdrc => [R, C, D, F],
+            Layout::Jkmi => [I, M, J, K],
+            Layout::Arrows => [Up, Down, Left, Right],
+            Layout::Wasd => [W, S, A, D],
+            Layout::Zqsd => [Z, S, Q, D],
+            Layout::Ijkl => [I, K, J, L],
+            Layout::Numpad => [Numpad8, Numpad2, Numpad4, Numpad6],
+        };
+        ScreenKeys {
+            up: vec![up],
+            down: vec![down],
+            left: vec![left],
+            right: vec![right],
+        }
+    }
+    pub fn next(&self) -> Self {
+        let i = Layout::ALL
+            .iter()
+            .position(|layout| layout == self)
+            .unwrap();
+        Layout::ALL[(i + 1) % Layout::ALL.len()]
+    }
+}
+
+// Keycode by its name, e.g. `"F"`, `"Up"` or `"Numpad8"`
+#[derive(Copy, Clone, Debug)]
+pub struct KeyName(pub Keycode);
+impl<'de> Deserialize<'de> for KeyName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse()
+            .map(KeyName)
+            .map_err(|_| serde::de::Error::custom(format!("unknown key `{name}`")))
+    }
+}
+
+// Config of a player's keys: a layout whose directions can be replaced
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlayerKeysConfig {
+    pub layout: Option<Layout>,
+    pub up: Option<Vec<KeyName>>,
+    pub down: Option<Vec<KeyName>>,
+    pub left: Option<Vec<KeyName>>,
+    pub right: Option<Vec<KeyName>>,
+}
+impl PlayerKeysConfig {
+    fn to_keys(&self, default_layout: Layout) -> ScreenKeys {
+        let mut keys = self.layout.unwrap_or(default_layout).keys();
+        let overrides = [&self.up, &self.down, &self.left, &self.right];
+        for (keycodes, names) in keys.directions_mut().into_iter().zip(overrides) {
+            if let Some(names) = names {
+                *keycodes = names.iter().map(|name| name.0).collect();
+            }
+        }
+        keys
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyBindings {
+    pub player1: ScreenKeys,
+    pub player2: ScreenKeys,
+}
+impl KeyBindings {
+    pub fn new(player1: &PlayerKeysConfig, player2: &PlayerKeysConfig) -> Self {
+        Self {
+            player1: player1.to_keys(Layout::Fdrc),
+            player2: player2.to_keys(Layout::Jkmi),
+        }
+    }
+    pub fn players(&self) -> [&ScreenKeys; 2] {
+        [&self.player1, &self.player2]
+    }
+    pub fn players_mut(&mut self) -> [&mut ScreenKeys; 2] {
+        [&mut self.player1, &mut self.player2]
+    }
+    // Keys of the d-pads of the first and second gamepad
+    pub fn gamepad_keys(&self) -> Vec<GamepadKeys> {
+        self.players()
+            .into_iter()
+            .map(ScreenKeys::gamepad_keys)
+            .collect()
+    }
+    // Every direction needs a key and no key may move two directions
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut used: Vec<(Keycode, String)> = Vec::new();
+        for (player, keys) in self.players().into_iter().enumerate() {
+            for (direction, keycodes) in ScreenKeys::DIRECTION_NAMES.iter().zip(keys.directions()) {
+                let name = format!("keys.player{}.{}", player + 1, direction);
+                ensure!(!keycodes.is_empty(), "{name} has no key");
+                for keycode in keycodes {
+                    if let Some((_, other)) = used.iter().find(|(used, _)| used == keycode) {
+                        bail!("{name} uses {keycode} which is already used by {other}");
+                    }
+                    used.push((*keycode, name.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+    // Write the bindings into the `[keys]` table of a config file, keeping the
+    // rest of the file and its comments as they are
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let text = if path.exists() {
+            fs::read_to_string(path)?
+        } else {
+            String::new()
+        };
+        let mut document: DocumentMut = text.parse()?;
+        let keys_table = document
+            .entry("keys")
+            .or_insert_with(|| {
+                let mut table = Table::new();
+                table.set_implicit(true);
+                Item::Table(table)
+            })
+            .as_table_like_mut()
+            .context("keys is not a table")?;
+        for (player, keys) in self.players().into_iter().enumerate() {
+            let name = format!("player{}", player + 1);
+            let table = keys_table
+                .entry(&name)
+                .or_insert(Item::Table(Table::new()))
+                .as_table_like_mut()
+                .with_context(|| format!("keys.{name} is not a table"))?;
+            for (direction, keycodes) in ScreenKeys::DIRECTION_NAMES.iter().zip(keys.directions()) {
+                let names: Array = keycodes.iter().map(|keycode| keycode.to_string()).collect();
+                table.insert(direction, value(names));
+            }
+        }
+        fs::write(path, document.to_string())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::{env, process};
+    use Keycode::*;
+
+    fn player(text: &str) -> PlayerKeysConfig {
+        toml::from_str(text).unwrap()
+    }
+
+    fn keys(up: &[Keycode], down: &[Keycode], left: &[Keycode], right: &[Keycode]) -> ScreenKeys {
+        ScreenKeys {
+            up: up.to_vec(),
+            down: down.to_vec(),
+            left: left.to_vec(),
+            right: right.to_vec(),
+        }
+    }
+
+    #[test]
+    fn to_keys() {
+        assert_eq!(player("").to_keys(Layout::Jkmi), Layout::Jkmi.keys());
+        assert_eq!(
+            player("layout = \"wasd\"").to_keys(Layout::Jkmi),
+            Layout::Wasd.keys()
+        );
+        assert_eq!(
+            player("layout = \"arrows\"\nup = [\"W\", \"Up\"]\nright = []").to_keys(Layout::Fdrc),
+            keys(&[W, Up], &[Down], &[Left], &[])
+        );
+        // Directions replace the default layout's keys too
+        assert_eq!(
+            player("down = [\"Numpad2\"]").to_keys(Layout::Fdrc),
+            keys(&[R], &[Numpad2], &[D], &[F])
+        );
+        assert!(toml::from_str::<PlayerKeysConfig>("up = [\"Nope\"]").is_err());
+    }
+
+    #[test]
+    fn validate() {
+        let mut key_bindings = KeyBindings::new(&player(""), &player(""));
+        key_bindings.validate().unwrap();
+
+        key_bindings.player2.left.push(F);
+        assert_eq!(
+            key_bindings.validate().unwrap_err().to_string(),
+            "keys.player2.left uses F which is already used by keys.player1.right"
+        );
+        key_bindings.player2.left = vec![J, J];
+        assert_eq!(
+            key_bindings.validate().unwrap_err().to_string(),
+            "keys.player2.left uses J which is already used by keys.player2.left"
+        );
+        key_bindings.player2.left = Vec::new();
+        assert_eq!(
+            key_bindings.validate().unwrap_err().to_string(),
+            "keys.player2.left has no key"
+        );
+    }
+
+    #[test]
+    fn gamepad_keys() {
+        let screen_keys = keys(&[W, Up], &[S], &[], &[D]);
+        let gamepad_keys = screen_keys.gamepad_keys();
+        assert_eq!(
+            [
+                gamepad_keys.up,
+                gamepad_keys.down,
+                gamepad_keys.left,
+                gamepad_keys.right
+            ],
+            [Some(W), Some(S), None, Some(D)]
+        );
+    }
+
+    #[test]
+    fn save_keeps_the_rest_of_the_file() {
+        let path = env::temp_dir().join(format!("vshooter-test-{}-keys.toml", process::id()));
+        let text = include_str!("../vshooter.example.toml");
+        fs::write(&path, text).unwrap();
+        let key_bindings = KeyBindings {
+            player1: keys(&[Up, W], &[Down], &[Left], &[Right]),
+            player2: Layout::Ijkl.keys(),
+        };
+        key_bindings.save(&path).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        let config: Config = toml::from_str(&saved).unwrap();
+        assert_eq!(config.key_bindings(), key_bindings);
+        // Comments and headers, the values of the keys tables change
+        for line in text
+            .lines()
+            .filter(|line| !line.contains(" = ") || line.starts_with('#'))
+        {
+            assert!(saved.contains(line), "{line:?} is lost");
+        }
+        assert_eq!(config.tick.tick_ms, 3);
+
+        // A new file gets only the keys
+        fs::remove_file(&path).unwrap();
+        key_bindings.save(&path).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(saved.starts_with("[keys.player1]\n"), "{saved}");
+        let config: Config = toml::from_str(&saved).unwrap();
+        assert_eq!(config.key_bindings(), key_bindings);
+    }
+}
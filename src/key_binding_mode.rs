@@ -0,0 +1,397 @@
+use crate::config::config;
+use crate::constant::MENU_BLINK_HALF_PERIOD;
+use crate::interface::{
+    debounce::{Button, ButtonEventKind},
+    led_pattern::Pattern,
+    Display, Interfaces,
+};
+use crate::key_binding::{KeyBindings, Layout, ScreenKeys};
+use crate::scene::{Input, Scene, SceneId, Session, Transition};
+use device_query::Keycode;
+use display_interface::DisplayError;
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    pixelcolor::BinaryColor,
+    prelude::*,
+    text::{Baseline, Text},
+};
+use itertools::Itertools;
+use std::time::Duration;
+
+// Height of a text line, as many lines as fit on the display are shown
+const ROW_HEIGHT: i32 = 10;
+// Cursor moves while button 1 is held after a long press
+const REPEAT_INTERVAL: Duration = Duration::from_millis(150);
+
+#[derive(Copy, Clone)]
+enum Item {
+    Layout(usize),
+    // Player and direction
+    Direction(usize, usize),
+    Save,
+    Exit,
+}
+
+// Key Binding Scene
+// Button 1 or Up/Down moves the cursor, holding button 1 keeps moving it.
+// Button 2 or Enter selects, a long press of button 2 or Escape goes back to
+// the menu. Selecting a direction waits for a key, which is added to the
+// direction or removed if it is already bound to it. Escape or a long press of
+// button 2 stops waiting without changing the direction.
+pub struct KeyBindingScene {
+    items: Vec<Item>,
+    cursor: usize,
+    waiting: bool,
+    message: Option<String>,
+    // Button 2 selects when released, unless the press was long
+    selecting: bool,
+    next_repeat: Option<Duration>,
+}
+impl KeyBindingScene {
+    pub fn new() -> Self {
+        let mut items = Vec::new();
+        for player in 0..2 {
+            items.push(Item::Layout(player));
+            for direction in 0..4 {
+                items.push(Item::Direction(player, direction));
+            }
+        }
+        items.push(Item::Save);
+        items.push(Item::Exit);
+        Self {
+            items,
+            cursor: 0,
+            waiting: false,
+            message: None,
+            selecting: false,
+            next_repeat: None,
+        }
+    }
+}
+
+impl<D: Display> Scene<D> for KeyBindingScene {
+    fn update(
+        &mut self,
+        interfaces: &mut Interfaces<D>,
+        session: &mut Session,
+        input: &Input,
+    ) -> anyhow::Result<Transition> {
+        let key_bindings = &mut session.key_bindings;
+        let pressed = &input.pressed;
+        let mut button1 = false;
+        let mut button2 = false;
+        let mut button2_long = false;
+        for event in &input.button_events {
+            match (event.button, event.kind) {
+                (Button::Button1, ButtonEventKind::Pressed) => button1 = true,
+                (Button::Button1, ButtonEventKind::LongPress) => {
+                    button1 = true;
+                    self.next_repeat = Some(event.at + REPEAT_INTERVAL);
+                }
+                (Button::Button1, ButtonEventKind::Held)
+                    if self.next_repeat.is_some_and(|at| event.at >= at) =>
+                {
+                    button1 = true;
+                    self.next_repeat = Some(event.at + REPEAT_INTERVAL);
+                }
+                (Button::Button1, ButtonEventKind::Released) => self.next_repeat = None,
+                (Button::Button2, ButtonEventKind::Pressed) => self.selecting = true,
+                (Button::Button2, ButtonEventKind::LongPress) if self.selecting => {
+                    self.selecting = false;
+                    button2_long = true;
+                }
+                (Button::Button2, ButtonEventKind::Released) if self.selecting => {
+                    self.selecting = false;
+                    button2 = true;
+                }
+                _ => (),
+            }
+        }
+
+        // Process
+        let items = &self.items;
+        let cursor = &mut self.cursor;
+        let message = &mut self.message;
+        if self.waiting {
+            if button2_long || pressed.contains(&Keycode::Escape) {
+                self.waiting = false;
+            } else if let (Some(keycode), Item::Direction(player, direction)) =
+                (pressed.first(), items[*cursor])
+            {
+                toggle_key(key_bindings, player, direction, *keycode);
+                *message = key_bindings.validate().err().map(|e| e.to_string());
+                self.waiting = false;
+            }
+        } else if button1 || pressed.contains(&Keycode::Down) {
+            *cursor = (*cursor + 1) % items.len();
+        } else if pressed.contains(&Keycode::Up) {
+            *cursor = (*cursor + items.len() - 1) % items.len();
+        } else if button2 || pressed.contains(&Keycode::Enter) {
+            match items[*cursor] {
+                Item::Layout(player) => {
+                    let keys = &mut key_bindings.players_mut()[player];
+                    let layout = layout_of(keys).map_or(Layout::ALL[0], |layout| layout.next());
+                    **keys = layout.keys();
+                    *message = key_bindings.validate().err().map(|e| e.to_string());
+                }
+                Item::Direction(_, _) => self.waiting = true,
+                Item::Save => {
+                    let path = config().save_path();
+                    *message = Some(
+                        match key_bindings
+                            .validate()
+                            .and_then(|_| key_bindings.save(&path))
+                        {
+                            Ok(()) => format!("Saved to {}", path.display()),
+                            Err(e) => e.to_string(),
+                        },
+                    );
+                }
+                Item::Exit => {
+                    if leave(key_bindings, message) {
+                        return Ok(Transition::To(SceneId::Menu));
+                    }
+                }
+            }
+        } else if (button2_long || pressed.contains(&Keycode::Escape))
+            && leave(key_bindings, message)
+        {
+            return Ok(Transition::To(SceneId::Menu));
+        }
+
+        // Leds
+        // the LED of the player whose item is selected blinks
+        let player = match items[*cursor] {
+            Item::Layout(player) | Item::Direction(player, _) => Some(player),
+            Item::Save | Item::Exit => None,
+        };
+        let pattern = |led_player| {
+            if player == Some(led_player) {
+                Pattern::Blink {
+                    half_period: MENU_BLINK_HALF_PERIOD,
+                }
+            } else {
+                Pattern::Off
+            }
+        };
+        interfaces.leds.led1.set(pattern(0));
+        interfaces.leds.led2.set(pattern(1));
+        Ok(Transition::Stay)
+    }
+
+    fn render(&self, session: &Session, display: &mut D) -> Result<(), DisplayError> {
+        let key_bindings = &session.key_bindings;
+        let character_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+        display.clear(BinaryColor::Off)?;
+        let lines = display.size().height as usize / ROW_HEIGHT as usize;
+        let rows = if self.message.is_some() {
+            lines - 1
+        } else {
+            lines
+        };
+        let first = self.cursor.saturating_sub(rows - 1);
+        for (row, (i, item)) in self
+            .items
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(rows)
+            .enumerate()
+        {
+            let marker = if i == self.cursor { ">" } else { " " };
+            let text = match item {
+                Item::Layout(player) => format!(
+                    "P{} layout: {}",
+                    player + 1,
+                    layout_of(key_bindings.players()[*player]).map_or("custom", |l| l.name())
+                ),
+                Item::Direction(player, direction) => {
+                    let keys = if self.waiting && i == self.cursor {
+                        "press a key".to_string()
+                    } else {
+                        key_bindings.players()[*player].directions()[*direction]
+                            .iter()
+                            .join(" ")
+                    };
+                    format!(
+                        "P{} {}: {}",
+                        player + 1,
+                        ScreenKeys::DIRECTION_NAMES[*direction],
+                        keys
+                    )
+                }
+                Item::Save => "Save".to_string(),
+                Item::Exit => "Exit".to_string(),
+            };
+            Text::with_baseline(
+                &format!("{marker}{text}"),
+                Point::new(0, row as i32 * ROW_HEIGHT),
+                character_style,
+                Baseline::Top,
+            )
+            .draw(display)?;
+        }
+        if let Some(message) = &self.message {
+            Text::with_baseline(
+                message,
+                Point::new(0, (lines as i32 - 1) * ROW_HEIGHT),
+                character_style,
+                Baseline::Top,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn exit(
+        &mut self,
+        interfaces: &mut Interfaces<D>,
+        session: &mut Session,
+    ) -> anyhow::Result<()> {
+        interfaces.keyboard.bind(&session.key_bindings);
+        interfaces.leds.led1.set(Pattern::Off);
+        interfaces.leds.led2.set(Pattern::Off);
+        interfaces.leds.tick();
+        Ok(())
+    }
+}
+
+// Layout whose keys are bound exactly
+fn layout_of(keys: &ScreenKeys) -> Option<Layout> {
+    Layout::ALL
+        .into_iter()
+        .find(|layout| &layout.keys() == keys)
+}
+
+// Add a key to a direction and take it from the others, or remove it if the
+// direction has other keys too
+fn toggle_key(key_bindings: &mut KeyBindings, player: usize, direction: usize, keycode: Keycode) {
+    let keycodes = &key_bindings.players()[player].directions()[direction];
+    if keycodes.contains(&keycode) {
+        if keycodes.len() > 1 {
+            key_bindings.players_mut()[player].directions_mut()[direction]
+                .retain(|other| *other != keycode);
+        }
+        return;
+    }
+    for keys in key_bindings.players_mut() {
+        for keycodes in keys.directions_mut() {
+            keycodes.retain(|other| *other != keycode);
+        }
+    }
+    key_bindings.players_mut()[player].directions_mut()[direction].push(keycode);
+}
+
+// Whether the menu can be left, the bindings must be valid
+fn leave(key_bindings: &KeyBindings, message: &mut Option<String>) -> bool {
+    match key_bindings.validate() {
+        Ok(()) => true,
+        Err(e) => {
+            *message = Some(e.to_string());
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interface::{
+        debounce::{ButtonEvent, ButtonEvents},
+        headless::HeadlessDisplay,
+        mock::{ScriptedButtons, TickCounter},
+        null::{NoBuzzer, NoKeys, NoLed},
+        Leds,
+    };
+
+    fn interfaces() -> Interfaces<HeadlessDisplay> {
+        Interfaces::new(
+            HeadlessDisplay::new(config().display.size.size(), None),
+            Box::new(ScriptedButtons::new(TickCounter::new(), Vec::new())),
+            Leds::new(Box::new(NoLed), Box::new(NoLed)),
+            Box::new(NoKeys),
+            Box::new(NoBuzzer),
+        )
+    }
+
+    fn update(
+        scene: &mut KeyBindingScene,
+        session: &mut Session,
+        pressed: &[Keycode],
+        button_events: ButtonEvents,
+    ) -> Transition {
+        let input = Input {
+            keycodes: pressed.to_vec(),
+            pressed: pressed.to_vec(),
+            button_events,
+        };
+        scene.update(&mut interfaces(), session, &input).unwrap()
+    }
+
+    fn long_press() -> ButtonEvents {
+        [ButtonEventKind::Pressed, ButtonEventKind::LongPress]
+            .map(|kind| ButtonEvent {
+                button: Button::Button2,
+                kind,
+                at: Duration::ZERO,
+            })
+            .to_vec()
+    }
+
+    #[test]
+    fn cancel_waiting_for_a_key() {
+        let mut scene = KeyBindingScene::new();
+        let mut session = Session::new(config().key_bindings(), None, None, None);
+        let bindings = session.key_bindings.clone();
+        // P1 up
+        update(&mut scene, &mut session, &[Keycode::Down], Vec::new());
+        for cancel in [vec![Keycode::Escape], Vec::new()] {
+            update(&mut scene, &mut session, &[Keycode::Enter], Vec::new());
+            assert!(scene.waiting);
+            let events = if cancel.is_empty() {
+                long_press()
+            } else {
+                Vec::new()
+            };
+            let transition = update(&mut scene, &mut session, &cancel, events);
+            assert_eq!(transition, Transition::Stay);
+            assert!(!scene.waiting);
+            assert_eq!(session.key_bindings, bindings);
+        }
+        update(&mut scene, &mut session, &[Keycode::Enter], Vec::new());
+        update(&mut scene, &mut session, &[Keycode::X], Vec::new());
+        assert!(!scene.waiting);
+        assert_eq!(session.key_bindings.player1.up, [Keycode::R, Keycode::X]);
+    }
+
+    #[test]
+    fn toggle() {
+        let mut key_bindings = config().key_bindings();
+        // Taken from player 2's right and added to player 1's up
+        toggle_key(&mut key_bindings, 0, 0, Keycode::K);
+        assert_eq!(key_bindings.player1.up, [Keycode::R, Keycode::K]);
+        assert!(key_bindings.player2.right.is_empty());
+        // Moved within a player
+        toggle_key(&mut key_bindings, 0, 1, Keycode::K);
+        assert_eq!(key_bindings.player1.up, [Keycode::R]);
+        assert_eq!(key_bindings.player1.down, [Keycode::C, Keycode::K]);
+        // Removed again
+        toggle_key(&mut key_bindings, 0, 1, Keycode::K);
+        assert_eq!(key_bindings.player1.down, [Keycode::C]);
+        // but not the last key of a direction
+        toggle_key(&mut key_bindings, 0, 1, Keycode::C);
+        assert_eq!(key_bindings.player1.down, [Keycode::C]);
+    }
+
+    #[test]
+    fn layouts() {
+        for layout in Layout::ALL {
+            assert_eq!(layout_of(&layout.keys()), Some(layout));
+        }
+        let mut keys = Layout::Wasd.keys();
+        keys.up.push(Keycode::Up);
+        assert_eq!(layout_of(&keys), None);
+        keys.up = vec![Keycode::Up];
+        assert_eq!(layout_of(&keys), None);
+    }
+}
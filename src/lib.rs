@@ -0,0 +1,25 @@
+// Game logic, scenes and interface backends of VShooter, used by the game
+// binary and the examples
+
+// Game objects are built with `new`, as they were when this was the binary
+#![allow(clippy::new_without_default)]
+
+pub mod collision;
+pub mod config;
+pub mod constant;
+pub mod diagnostics_mode;
+pub mod game;
+pub mod interface;
+pub mod key_binding;
+pub mod key_binding_mode;
+pub mod loading_mode;
+pub mod menu_mode;
+pub mod object;
+pub mod replay;
+pub mod replay_mode;
+pub mod results_mode;
+pub mod scene;
+pub mod shooting_mode;
+pub mod sound;
+pub mod timestep;
+pub mod title_mode;
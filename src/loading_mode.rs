@@ -1,4 +1,9 @@
-use crate::interface::Display;
+use crate::config::config;
+use crate::constant::{BOOT_HOLD_TIME, BOOT_RING_TIME};
+use crate::interface::{debounce::Button, Display, Interfaces};
+use crate::scene::{Input, Scene, SceneId, Session, Transition};
+use device_query::Keycode;
+use display_interface::DisplayError;
 use embedded_graphics::{
     mono_font::{ascii::FONT_10X20, MonoTextStyle},
     pixelcolor::BinaryColor,
@@ -6,9 +11,50 @@ use embedded_graphics::{
     primitives::{Arc, PrimitiveStyleBuilder, StrokeAlignment},
     text::{Alignment, Baseline, Text, TextStyleBuilder},
 };
-use std::{thread, time::Duration};
 
-pub fn loading_ring(display: &mut Display) {
+// Boot scene, a progress ring fills up and stays for a moment. Holding both
+// buttons or Tab by then opens the key bindings instead of the title.
+pub struct BootScene {
+    ticks: u64,
+}
+impl BootScene {
+    pub fn new() -> Self {
+        Self { ticks: 0 }
+    }
+}
+
+impl<D: Display> Scene<D> for BootScene {
+    fn update(
+        &mut self,
+        interfaces: &mut Interfaces<D>,
+        _session: &mut Session,
+        input: &Input,
+    ) -> anyhow::Result<Transition> {
+        let tick = &config().tick;
+        self.ticks += 1;
+        if self.ticks < tick.ticks(BOOT_RING_TIME) + tick.ticks(BOOT_HOLD_TIME) {
+            return Ok(Transition::Stay);
+        }
+        Ok(
+            if (interfaces.buttons.is_pressed(Button::Button1)
+                && interfaces.buttons.is_pressed(Button::Button2))
+                || input.keycodes.contains(&Keycode::Tab)
+            {
+                Transition::To(SceneId::KeyBinding)
+            } else {
+                Transition::To(SceneId::Title)
+            },
+        )
+    }
+
+    fn render(&self, _session: &Session, display: &mut D) -> Result<(), DisplayError> {
+        let progress = (self.ticks * 100 / config().tick.ticks(BOOT_RING_TIME)).min(100);
+        loading_ring(display, progress as u32)
+    }
+}
+
+// Ring and percentage of `progress`
+fn loading_ring<D: Display>(display: &mut D, progress: u32) -> Result<(), DisplayError> {
     let arc_stroke = PrimitiveStyleBuilder::new()
         .stroke_color(BinaryColor::On)
         .stroke_width(5)
@@ -20,29 +66,27 @@ pub fn loading_ring(display: &mut Display) {
         .alignment(Alignment::Center)
         .build();
 
-    for progress in 0..=100 {
-        display.clear(BinaryColor::Off).unwrap();
-        let sweep = progress as f32 * 360.0 / 100.0;
-
-        // Draw an arc with a 5px wide stroke.
-        Arc::new(Point::new(2 + 32, 2), 64 - 4, 90.0.deg(), sweep.deg())
-            .into_styled(arc_stroke)
-            .draw(display)
-            .unwrap();
-
-        // Draw centered text.
-        let text = format!("{}%", progress);
-        Text::with_text_style(
-            &text,
-            display.bounding_box().center(),
-            character_style,
-            text_style,
-        )
-        .draw(display)
-        .unwrap();
+    // Largest ring on the display, 2px from the edges
+    let size = display.size();
+    let diameter = size.width.min(size.height) - 4;
+    let top_left = Point::new((size.width - diameter) as i32 / 2, 2);
 
-        display.flush().unwrap();
+    display.clear(BinaryColor::Off)?;
+    let sweep = progress as f32 * 360.0 / 100.0;
 
-        thread::sleep(Duration::from_micros(1));
-    }
+    // Draw an arc with a 5px wide stroke.
+    Arc::new(top_left, diameter, 90.0.deg(), sweep.deg())
+        .into_styled(arc_stroke)
+        .draw(display)?;
+
+    // Draw centered text.
+    let text = format!("{}%", progress);
+    Text::with_text_style(
+        &text,
+        display.bounding_box().center(),
+        character_style,
+        text_style,
+    )
+    .draw(display)?;
+    Ok(())
 }
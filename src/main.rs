@@ -1,40 +1,426 @@
-mod constant;
-mod interface;
-mod loading_mode;
-mod object;
-mod shooting_mode;
-
-use interface::{Buttons, Interfaces, Keyboard, Leds};
-use loading_mode::loading_ring;
-use rppal::{gpio::Gpio, i2c::I2c};
-use shooting_mode::shooting;
-use ssd1306::{prelude::*, I2CDisplayInterface, Ssd1306};
-use std::{thread::sleep, time::Duration};
-
-fn main() {
-    // Initialize display
-    let i2c = I2c::new().unwrap();
-    let i2c_interface = I2CDisplayInterface::new(i2c);
-    let mut display = Ssd1306::new(i2c_interface, DisplaySize128x64, DisplayRotation::Rotate0)
-        .into_buffered_graphics_mode();
-    display.init().unwrap();
-    // Initialize interfaces
-    let gpio = Gpio::new().unwrap();
+use anyhow::{bail, Context};
+use config::{config, Config, PanelSize, DEFAULT_CONFIG_PATH};
+#[cfg(feature = "rpi")]
+use config::{Bus, Controller};
+#[cfg(feature = "rpi")]
+use interface::rpi::{new_leds, new_sh1106, new_ssd1306, new_ssd1306_spi, GpioButtons, PwmBuzzer};
+use interface::{
+    evdev::EvdevKeyboard,
+    gif_recorder::GifRecorder,
+    headless::{FrameDump, HeadlessDisplay, ImageFormat},
+    mock::{
+        new_mock_ssd1306, BuzzerLog, LedLog, MockSpi, RecordingBuzzer, RecordingLed,
+        ScriptedButtons, SpiLog, TickCounter,
+    },
+    null::{NoBuzzer, NoKeys, NoLed},
+    terminal::{Glyphs, TerminalDisplay},
+    terminal_keyboard::TerminalKeyboard,
+    x11::X11Keyboard,
+    Buttons, Buzzer, Display, Interfaces, Keyboard, Leds, Level,
+};
+use raspi_work::{config, interface, replay, scene};
+use replay::Replay;
+#[cfg(feature = "rpi")]
+use rppal::gpio::Gpio;
+use scene::{run_scenes, SceneId, Session};
+#[cfg(feature = "rpi")]
+use ssd1306::prelude::DisplaySize;
+use ssd1306::prelude::{DisplaySize128x32, DisplaySize128x64};
+use std::{
+    env,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+// Where the game is drawn
+enum DisplayBackend {
+    Oled,
+    Terminal(Glyphs),
+    Headless(Option<FrameDump>),
+    // SSD1306 driver writing to a mock SPI bus
+    MockSpi,
+}
+impl DisplayBackend {
+    fn from_args() -> anyhow::Result<Self> {
+        Ok(match arg_value("--display").as_deref().unwrap_or("oled") {
+            "oled" => Self::Oled,
+            "braille" => Self::Terminal(Glyphs::Braille),
+            "half-block" => Self::Terminal(Glyphs::HalfBlock),
+            "headless" => Self::Headless(frame_dump_from_args()?),
+            "mock-spi" => Self::MockSpi,
+            name => bail!(
+                "unknown display `{name}` (expected oled, braille, half-block, headless or mock-spi)"
+            ),
+        })
+    }
+}
+
+// `--dump-dir <dir> [--dump-every <n>] [--dump-format pbm|png|both]`
+fn frame_dump_from_args() -> anyhow::Result<Option<FrameDump>> {
+    let Some(directory) = arg_value("--dump-dir") else {
+        return Ok(None);
+    };
+    let every = match arg_value("--dump-every") {
+        Some(every) => every.parse()?,
+        None => 1,
+    };
+    let formats = match arg_value("--dump-format").as_deref().unwrap_or("png") {
+        "pbm" => vec![ImageFormat::Pbm],
+        "png" => vec![ImageFormat::Png],
+        "both" => vec![ImageFormat::Pbm, ImageFormat::Png],
+        format => bail!("unknown dump format `{format}` (expected pbm, png or both)"),
+    };
+    Ok(Some(FrameDump::new(
+        PathBuf::from(directory),
+        every,
+        formats,
+    )))
+}
+
+// Where key presses are read from, `--evdev-replay <file>[,<file>...]`
+// replays captured input events
+fn keyboard_from_args(default: &str) -> anyhow::Result<Box<dyn Keyboard>> {
+    // Rebound by the key binding menu when the keys change
+    let key_bindings = config().key_bindings();
+    let gamepad_keys = key_bindings.gamepad_keys();
+    if let Some(paths) = arg_value("--evdev-replay") {
+        let paths: Vec<PathBuf> = paths.split(',').map(PathBuf::from).collect();
+        return Ok(Box::new(EvdevKeyboard::replay(&paths, gamepad_keys)?));
+    }
+    Ok(
+        match arg_value("--keyboard").as_deref().unwrap_or(default) {
+            "x11" => Box::new(X11Keyboard::new()),
+            "terminal" => {
+                TerminalKeyboard::check_bindings(&key_bindings)?;
+                Box::new(TerminalKeyboard::new()?)
+            }
+            "evdev" => Box::new(EvdevKeyboard::open(gamepad_keys)?),
+            "none" => Box::new(NoKeys),
+            name => bail!("unknown keyboard `{name}` (expected x11, terminal, evdev or none)"),
+        },
+    )
+}
+
+// Buttons, LEDs and buzzer for the displays without GPIO
+struct SoftwareGpio {
+    ticks: TickCounter,
+    buttons: Box<dyn Buttons>,
+    leds: Leds,
+    led_logs: Option<(LedLog, LedLog)>,
+    buzzer: Box<dyn Buzzer>,
+    buzzer_log: Option<BuzzerLog>,
+}
+impl SoftwareGpio {
+    // `--buttons <script>` scripts the button levels per tick,
+    // `--led-log <file>` records the LED writes and `--buzzer-log <file>` the
+    // notes
+    fn from_args() -> anyhow::Result<Self> {
+        let ticks = TickCounter::new();
+        let buttons: Box<dyn Buttons> = match arg_value("--buttons") {
+            Some(path) => Box::new(ScriptedButtons::parse(
+                ticks.clone(),
+                &fs::read_to_string(path)?,
+            )?),
+            // Never pressed, but still read every tick to advance the ticks
+            // of the logs
+            None => Box::new(ScriptedButtons::new(ticks.clone(), Vec::new())),
+        };
+        let (leds, led_logs) = match arg_value("--led-log") {
+            Some(_) => {
+                let (led1, led2) = (
+                    RecordingLed::new(ticks.clone()),
+                    RecordingLed::new(ticks.clone()),
+                );
+                let logs = (led1.log(), led2.log());
+                (Leds::new(Box::new(led1), Box::new(led2)), Some(logs))
+            }
+            None => (Leds::new(Box::new(NoLed), Box::new(NoLed)), None),
+        };
+        let (buzzer, buzzer_log): (Box<dyn Buzzer>, _) = match arg_value("--buzzer-log") {
+            Some(_) => {
+                let buzzer = RecordingBuzzer::new(ticks.clone());
+                let log = buzzer.log();
+                (Box::new(buzzer), Some(log))
+            }
+            None => (Box::new(NoBuzzer), None),
+        };
+        Ok(Self {
+            ticks,
+            buttons,
+            leds,
+            led_logs,
+            buzzer,
+            buzzer_log,
+        })
+    }
+}
+
+// One `<led> <tick> <level>` per line
+fn save_led_logs(led_logs: Option<(LedLog, LedLog)>) -> anyhow::Result<()> {
+    let (Some(path), Some((led1, led2))) = (arg_value("--led-log"), led_logs) else {
+        return Ok(());
+    };
+    let mut text = String::new();
+    for (name, log) in [("led1", led1), ("led2", led2)] {
+        for write in log.borrow().iter() {
+            writeln!(text, "{} {} {:?}", name, write.tick, write.level)?;
+        }
+    }
+    fs::write(path, text)?;
+    Ok(())
+}
+
+// One `<tick> <frequency in Hz, or rest> <duration in ms>` per line
+fn save_buzzer_log(buzzer_log: Option<BuzzerLog>) -> anyhow::Result<()> {
+    let (Some(path), Some(log)) = (arg_value("--buzzer-log"), buzzer_log) else {
+        return Ok(());
+    };
+    let mut text = String::new();
+    for write in log.borrow().iter() {
+        let frequency = write
+            .note
+            .frequency
+            .map_or("rest".to_string(), |f| f.to_string());
+        writeln!(
+            text,
+            "{} {} {}",
+            write.tick,
+            frequency,
+            write.note.duration.as_millis()
+        )?;
+    }
+    fs::write(path, text)?;
+    Ok(())
+}
+
+// One `<tick> cmd|data <bytes in hex>` per line
+fn save_spi_log(spi_log: SpiLog) -> anyhow::Result<()> {
+    let Some(path) = arg_value("--spi-log") else {
+        return Ok(());
+    };
+    let mut text = String::new();
+    for write in spi_log.borrow().iter() {
+        let kind = match write.dc {
+            Level::Low => "cmd",
+            Level::High => "data",
+        };
+        write!(text, "{} {}", write.tick, kind)?;
+        for byte in write.bytes.iter() {
+            write!(text, " {byte:02x}")?;
+        }
+        writeln!(text)?;
+    }
+    fs::write(path, text)?;
+    Ok(())
+}
+
+// `--seed <n>`, or `random.seed` of the config
+fn seed_from_args() -> anyhow::Result<Option<u64>> {
+    Ok(match arg_value("--seed") {
+        Some(seed) => Some(seed.parse()?),
+        None => config().random.seed,
+    })
+}
+
+// Value following `name` on the command line
+fn arg_value(name: &str) -> Option<String> {
+    let mut args = env::args().skip_while(|arg| arg != name);
+    args.next().and(args.next())
+}
+
+// `--config <file>`, or `vshooter.toml` if it exists
+fn load_config() -> anyhow::Result<()> {
+    let config = match arg_value("--config") {
+        Some(path) => Config::load(Path::new(&path))?,
+        None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+            Config::load(Path::new(DEFAULT_CONFIG_PATH))?
+        }
+        None => Config::default(),
+    };
+    config::init(config);
+    Ok(())
+}
+
+// Ctrl-C ended the game, the exit status tells once everything is saved and
+// the terminal is restored
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+fn main() -> anyhow::Result<()> {
+    play()?;
+    if INTERRUPTED.load(Ordering::Relaxed) {
+        process::exit(130);
+    }
+    Ok(())
+}
+
+fn play() -> anyhow::Result<()> {
+    load_config()?;
+    let display_size = config().display.size.size();
+    // `--record-dir <dir>` saves every match as a GIF
+    let record_dir = arg_value("--record-dir").map(PathBuf::from);
+    // `--matches <n>` exits once the results of the nth match time out
+    let matches = arg_value("--matches").map(|n| n.parse()).transpose()?;
+    match DisplayBackend::from_args()? {
+        // Initialize display
+        DisplayBackend::Oled => run_on_oled(record_dir, matches),
+        DisplayBackend::Terminal(glyphs) => run_on_software_gpio(
+            TerminalDisplay::new(display_size, glyphs),
+            SoftwareGpio::from_args()?,
+            "terminal",
+            record_dir,
+            matches,
+        ),
+        DisplayBackend::Headless(dump) => run_on_software_gpio(
+            HeadlessDisplay::new(display_size, dump),
+            SoftwareGpio::from_args()?,
+            "none",
+            record_dir,
+            matches,
+        ),
+        // `--spi-log <file>` saves the bytes sent to the display
+        DisplayBackend::MockSpi => {
+            let gpio = SoftwareGpio::from_args()?;
+            let spi = MockSpi::new(gpio.ticks.clone());
+            let spi_log = spi.log();
+            match config().display.size {
+                PanelSize::Size128x64 => run_on_software_gpio(
+                    new_mock_ssd1306(spi, DisplaySize128x64),
+                    gpio,
+                    "none",
+                    record_dir,
+                    matches,
+                )?,
+                PanelSize::Size128x32 => run_on_software_gpio(
+                    new_mock_ssd1306(spi, DisplaySize128x32),
+                    gpio,
+                    "none",
+                    record_dir,
+                    matches,
+                )?,
+            }
+            save_spi_log(spi_log)
+        }
+    }
+}
+
+// Play with scripted or released buttons, `keyboard` unless `--keyboard` is given
+fn run_on_software_gpio<D: Display>(
+    display: D,
+    gpio: SoftwareGpio,
+    keyboard: &str,
+    record_dir: Option<PathBuf>,
+    matches: Option<u32>,
+) -> anyhow::Result<()> {
     let mut interfaces = Interfaces::new(
-        display,
-        Buttons::new(&gpio),
-        Leds::new(&gpio),
-        Keyboard::new(),
+        GifRecorder::new(display, record_dir),
+        gpio.buttons,
+        gpio.leds,
+        keyboard_from_args(keyboard)?,
+        gpio.buzzer,
     );
+    run(&mut interfaces, matches)?;
+    save_led_logs(gpio.led_logs)?;
+    save_buzzer_log(gpio.buzzer_log)
+}
+
+// Play on the OLED of `display.controller` and `display.size`
+#[cfg(feature = "rpi")]
+fn run_on_oled(record_dir: Option<PathBuf>, matches: Option<u32>) -> anyhow::Result<()> {
+    match (config().display.controller, config().display.size) {
+        (Controller::Ssd1306, PanelSize::Size128x64) => {
+            run_on_ssd1306(DisplaySize128x64, record_dir, matches)
+        }
+        (Controller::Ssd1306, PanelSize::Size128x32) => {
+            run_on_ssd1306(DisplaySize128x32, record_dir, matches)
+        }
+        (Controller::Sh1106, PanelSize::Size128x64) => run_on_gpio(
+            new_sh1106(sh1106::displaysize::DisplaySize::Display128x64),
+            record_dir,
+            matches,
+        ),
+        (Controller::Sh1106, PanelSize::Size128x32) => run_on_gpio(
+            new_sh1106(sh1106::displaysize::DisplaySize::Display128x32),
+            record_dir,
+            matches,
+        ),
+    }
+}
 
-    loop {
-        // Mode: Progress Ring
-        loading_ring(&mut interfaces.display);
+// Hosts other than the Pi are built without the `rpi` feature
+#[cfg(not(feature = "rpi"))]
+fn run_on_oled(_record_dir: Option<PathBuf>, _matches: Option<u32>) -> anyhow::Result<()> {
+    bail!("built without the `rpi` feature, the oled display is not available")
+}
 
-        // Wait 0.5 sec
-        sleep(Duration::from_millis(500));
+// SSD1306 on the bus chosen by `display.interface`
+#[cfg(feature = "rpi")]
+fn run_on_ssd1306<SIZE: DisplaySize>(
+    size: SIZE,
+    record_dir: Option<PathBuf>,
+    matches: Option<u32>,
+) -> anyhow::Result<()> {
+    match config().display.interface {
+        Bus::I2c => run_on_gpio(new_ssd1306(size), record_dir, matches),
+        Bus::Spi => run_on_gpio(new_ssd1306_spi(size), record_dir, matches),
+    }
+}
 
-        // Mode: Shouting
-        shooting(&mut interfaces);
+// Play on an OLED with the buttons and LEDs on GPIO
+#[cfg(feature = "rpi")]
+fn run_on_gpio<D: Display>(
+    display: D,
+    record_dir: Option<PathBuf>,
+    matches: Option<u32>,
+) -> anyhow::Result<()> {
+    // Initialize interfaces
+    let gpio = Gpio::new().unwrap();
+    let (led1, led2) = new_leds(&gpio);
+    let buzzer: Box<dyn Buzzer> = match PwmBuzzer::new(&gpio) {
+        Some(buzzer) => Box::new(buzzer),
+        None => Box::new(NoBuzzer),
+    };
+    let mut interfaces = Interfaces::new(
+        GifRecorder::new(display, record_dir),
+        Box::new(GpioButtons::new(&gpio)),
+        Leds::new(Box::new(led1), Box::new(led2)),
+        keyboard_from_args("x11")?,
+        buzzer,
+    );
+    run(&mut interfaces, matches)
+}
+
+// Play `matches` matches, or forever without it
+fn run<D: Display>(
+    interfaces: &mut Interfaces<GifRecorder<D>>,
+    matches: Option<u32>,
+) -> anyhow::Result<()> {
+    // `--replay <file>` plays a recorded match back instead, `--view <file>`
+    // opens it in the viewer
+    let (first, replay) = match (arg_value("--replay"), arg_value("--view")) {
+        (Some(path), _) => (SceneId::Replay, Some(Replay::load(Path::new(&path))?)),
+        (None, Some(path)) => (SceneId::Viewer, Some(Replay::load(Path::new(&path))?)),
+        (None, None) => (SceneId::Boot, None),
+    };
+    // `--replay-dir <dir>` saves the inputs of every match to replay it,
+    // created now so a bad path fails before the first match rather than after
+    let replay_dir = arg_value("--replay-dir").map(PathBuf::from);
+    if let Some(directory) = &replay_dir {
+        fs::create_dir_all(directory)
+            .with_context(|| format!("cannot create {}", directory.display()))?;
+    }
+    let mut session = Session::new(
+        config().key_bindings(),
+        seed_from_args()?,
+        replay_dir,
+        matches,
+    );
+    session.replay = replay;
+    run_scenes(interfaces, &mut session, first)?;
+    if interfaces.keyboard.quit_requested() {
+        INTERRUPTED.store(true, Ordering::Relaxed);
     }
+    Ok(())
 }
@@ -0,0 +1,76 @@
+use crate::interface::{Display, Interfaces};
+use crate::scene::{Input, Press, Presses, Scene, SceneId, Session, Transition};
+use device_query::Keycode;
+use display_interface::DisplayError;
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    pixelcolor::BinaryColor,
+    prelude::*,
+    text::{Baseline, Text},
+};
+
+// Height of a text line, the items scroll when they do not all fit
+const ROW_HEIGHT: i32 = 10;
+
+// Items and the scenes they open
+const ITEMS: [(&str, SceneId); 4] = [
+    ("Play", SceneId::Match),
+    ("Key bindings", SceneId::KeyBinding),
+    ("Diagnostics", SceneId::Diagnostics),
+    ("Title", SceneId::Title),
+];
+
+// Menu scene
+// Button 1 or Up/Down moves the cursor, button 2 or Enter opens the item. A
+// long press of button 2 or Escape goes back to the title.
+pub struct MenuScene {
+    presses: Presses,
+    cursor: usize,
+}
+impl MenuScene {
+    pub fn new() -> Self {
+        Self {
+            presses: Presses::new(),
+            cursor: 0,
+        }
+    }
+}
+
+impl<D: Display> Scene<D> for MenuScene {
+    fn update(
+        &mut self,
+        _interfaces: &mut Interfaces<D>,
+        _session: &mut Session,
+        input: &Input,
+    ) -> anyhow::Result<Transition> {
+        let [button1, button2] = self.presses.read(&input.button_events);
+        if button1 == Press::Short || input.pressed.contains(&Keycode::Down) {
+            self.cursor = (self.cursor + 1) % ITEMS.len();
+        } else if input.pressed.contains(&Keycode::Up) {
+            self.cursor = (self.cursor + ITEMS.len() - 1) % ITEMS.len();
+        } else if button2 == Press::Short || input.pressed.contains(&Keycode::Enter) {
+            return Ok(Transition::To(ITEMS[self.cursor].1));
+        } else if button2 == Press::Long || input.pressed.contains(&Keycode::Escape) {
+            return Ok(Transition::To(SceneId::Title));
+        }
+        Ok(Transition::Stay)
+    }
+
+    fn render(&self, _session: &Session, display: &mut D) -> Result<(), DisplayError> {
+        let character_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+        display.clear(BinaryColor::Off)?;
+        let rows = display.size().height as usize / ROW_HEIGHT as usize;
+        let first = self.cursor.saturating_sub(rows - 1);
+        for (row, (i, (name, _))) in ITEMS.iter().enumerate().skip(first).take(rows).enumerate() {
+            let marker = if i == self.cursor { ">" } else { " " };
+            Text::with_baseline(
+                &format!("{marker}{name}"),
+                Point::new(0, row as i32 * ROW_HEIGHT),
+                character_style,
+                Baseline::Top,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+}
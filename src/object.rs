@@ -1,29 +1,13 @@
-use crate::constant::{DISPLAY_SIZE_X, EMIT_PROBABILITY, EMIT_TICK_SIZE, LASER_SPAWN_POSITION};
-use crate::interface::{ButtonLevels, Display, Interfaces, Keycodes, Led};
-use device_query::Keycode;
+use crate::config::{config, PoolOverflow};
+use crate::constant::{CENTER_GAP, PLAYER_START_X};
+use crate::interface::Keycodes;
+use crate::key_binding::{KeyBindings, ScreenKeys};
 use embedded_graphics::{
     pixelcolor::BinaryColor,
     prelude::*,
     primitives::{PrimitiveStyleBuilder, Rectangle, Triangle},
 };
-use rand::Rng;
-use rppal::gpio::Level;
-use rppal::gpio::Level::*;
-
-pub struct Status {
-    keycodes: Keycodes,
-    pub button_levels: ButtonLevels,
-}
-impl Status {
-    pub fn new(interfaces: &mut Interfaces) -> Self {
-        let keycodes = interfaces.keyboard.get_keycodes();
-        let button_levels = interfaces.buttons.get_levels();
-        Self {
-            keycodes,
-            button_levels,
-        }
-    }
-}
+use rand::{rngs::StdRng, Rng};
 
 #[derive(Debug)]
 pub struct RelativeDirections {
@@ -43,7 +27,7 @@ impl RelativeDirections {
     }
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Position {
     pub x: i32,
     pub y: i32,
@@ -54,8 +38,51 @@ impl Position {
     }
 }
 
+// Where an object moved on the last tick
+#[derive(Copy, Clone, Debug)]
+pub struct Motion {
+    pub from: Position,
+    pub to: Position,
+}
+
+// Arena struct
+// Geometry of the field, derived from the display size
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Arena {
+    pub width: i32,
+    pub height: i32,
+}
+impl Arena {
+    pub fn new(size: Size) -> Self {
+        Self {
+            width: size.width as i32,
+            height: size.height as i32,
+        }
+    }
+    // Where a player facing `direction` starts
+    fn start_position(&self, direction: AbsoluteDirection) -> Position {
+        let x = match direction {
+            AbsoluteDirection::XPlus => PLAYER_START_X,
+            AbsoluteDirection::XMinus => self.width - PLAYER_START_X,
+        };
+        Position::new(x, self.height / 2)
+    }
+    // How far a player facing `direction` can go forward
+    fn forward_limit(&self, direction: AbsoluteDirection) -> i32 {
+        match direction {
+            AbsoluteDirection::XPlus => self.width / 2 - CENTER_GAP,
+            AbsoluteDirection::XMinus => self.width / 2 + CENTER_GAP,
+        }
+    }
+    // Whether `position` is on the display or within `margin` around it
+    pub fn contains(&self, position: &Position, margin: i32) -> bool {
+        (-margin < position.x && position.x < self.width + margin)
+            && (-margin < position.y && position.y < self.height + margin)
+    }
+}
+
 // Objects type
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Team {
     Mono,
     Di,
@@ -67,30 +94,50 @@ pub enum AbsoluteDirection {
     XMinus,
 }
 
+// Player keys struct
+pub struct PlayerKeys {
+    pub forward: Keycodes,
+    pub backward: Keycodes,
+    pub left: Keycodes,
+    pub right: Keycodes,
+}
+impl PlayerKeys {
+    // Keys of a player facing `direction`
+    pub fn from_screen(keys: &ScreenKeys, direction: AbsoluteDirection) -> Self {
+        let keys = keys.clone();
+        match direction {
+            AbsoluteDirection::XPlus => Self {
+                forward: keys.right,
+                backward: keys.left,
+                left: keys.up,
+                right: keys.down,
+            },
+            AbsoluteDirection::XMinus => Self {
+                forward: keys.left,
+                backward: keys.right,
+                left: keys.down,
+                right: keys.up,
+            },
+        }
+    }
+}
+
 // Players struct
 pub struct Players {
     pub player1: Player,
     pub player2: Player,
 }
 impl Players {
-    pub fn new() -> Self {
+    pub fn new(key_bindings: &KeyBindings, arena: Arena) -> Self {
         let player1 = Player::new(
-            10,
-            32,
+            arena,
             AbsoluteDirection::XPlus,
-            vec![Keycode::F],
-            vec![Keycode::D],
-            vec![Keycode::R],
-            vec![Keycode::C],
+            PlayerKeys::from_screen(&key_bindings.player1, AbsoluteDirection::XPlus),
         );
         let player2 = Player::new(
-            128 - 10,
-            32,
+            arena,
             AbsoluteDirection::XMinus,
-            vec![Keycode::J],
-            vec![Keycode::K],
-            vec![Keycode::M],
-            vec![Keycode::I],
+            PlayerKeys::from_screen(&key_bindings.player2, AbsoluteDirection::XMinus),
         );
         Self { player1, player2 }
     }
@@ -99,51 +146,44 @@ impl Players {
 pub struct Player {
     x: i32,
     y: i32,
+    // Position before the last tick
+    last_position: Position,
     direction: AbsoluteDirection,
-    forward_keys: Keycodes,
-    backward_keys: Keycodes,
-    left_keys: Keycodes,
-    right_keys: Keycodes,
+    keys: PlayerKeys,
     speed: i32,
     interval: u8,
+    arena: Arena,
 }
 impl Player {
-    pub fn new(
-        x: i32,
-        y: i32,
-        direction: AbsoluteDirection,
-        forward_keys: Keycodes,
-        backward_keys: Keycodes,
-        left_keys: Keycodes,
-        right_keys: Keycodes,
-    ) -> Self {
+    pub fn new(arena: Arena, direction: AbsoluteDirection, keys: PlayerKeys) -> Self {
         let speed = 1;
+        let Position { x, y } = arena.start_position(direction);
         Self {
             x,
             y,
+            last_position: Position::new(x, y),
             direction,
-            forward_keys,
-            backward_keys,
-            left_keys,
-            right_keys,
+            keys,
             speed,
             interval: 0,
+            arena,
         }
     }
 
-    pub fn tick(&mut self, status: &Status) {
+    pub fn tick(&mut self, keycodes: &Keycodes) {
+        self.last_position = self.get_position();
         let mut directions = RelativeDirections::new();
-        for key in status.keycodes.iter() {
-            if self.forward_keys.contains(key) {
+        for key in keycodes.iter() {
+            if self.keys.forward.contains(key) {
                 directions.forward = true;
             }
-            if self.backward_keys.contains(key) {
+            if self.keys.backward.contains(key) {
                 directions.backward = true;
             }
-            if self.left_keys.contains(key) {
+            if self.keys.left.contains(key) {
                 directions.left = true;
             }
-            if self.right_keys.contains(key) {
+            if self.keys.right.contains(key) {
                 directions.right = true;
             }
         }
@@ -163,7 +203,10 @@ impl Player {
         }
     }
 
-    pub fn draw(&mut self, display: &mut Display) {
+    pub fn draw<D: DrawTarget<Color = BinaryColor>>(
+        &self,
+        display: &mut D,
+    ) -> Result<(), D::Error> {
         let style = PrimitiveStyleBuilder::new()
             .fill_color(BinaryColor::On)
             .build();
@@ -171,7 +214,6 @@ impl Player {
         Rectangle::new(Point::new(self.x - 3, self.y - 3), Size::new(7, 7))
             .into_styled(style)
             .draw(display)
-            .unwrap();
     }
 
     fn move_to(&mut self, x: i32, y: i32) {
@@ -180,12 +222,12 @@ impl Player {
     }
     fn move_by(&mut self, dx: i32, dy: i32) {
         let new_pos = Position::new(self.x + dx, self.y + dy);
-        let new_dx = if !(0 <= new_pos.x && new_pos.x <= 128) {
+        let new_dx = if !(0 <= new_pos.x && new_pos.x <= self.arena.width) {
             0
         } else {
             dx
         };
-        let new_dy = if !(0 <= new_pos.y && new_pos.y <= 64) {
+        let new_dy = if !(0 <= new_pos.y && new_pos.y <= self.arena.height) {
             0
         } else {
             dy
@@ -193,15 +235,29 @@ impl Player {
         self.move_to(self.x + new_dx, self.y + new_dy);
     }
     fn move_relative(&mut self, forward: i32, left: i32) {
+        let limit = self.arena.forward_limit(self.direction);
         let (dx, dy) = match self.direction {
-            AbsoluteDirection::XPlus => (if self.x + forward < 50 { forward } else { 0 }, -left),
-            AbsoluteDirection::XMinus => (if self.x - forward > 78 { -forward } else { 0 }, left),
+            AbsoluteDirection::XPlus => (if self.x + forward < limit { forward } else { 0 }, -left),
+            AbsoluteDirection::XMinus => (
+                if self.x - forward > limit {
+                    -forward
+                } else {
+                    0
+                },
+                left,
+            ),
         };
         self.move_by(dx * 2, dy);
     }
     pub fn get_position(&self) -> Position {
         Position::new(self.x, self.y)
     }
+    pub fn get_motion(&self) -> Motion {
+        Motion {
+            from: self.last_position,
+            to: self.get_position(),
+        }
+    }
 }
 
 // Guns
@@ -210,9 +266,9 @@ pub struct Guns {
     pub gun2: Gun,
 }
 impl Guns {
-    pub fn new() -> Self {
-        let gun1 = Gun::new(AbsoluteDirection::XPlus);
-        let gun2 = Gun::new(AbsoluteDirection::XMinus);
+    pub fn new(arena: Arena) -> Self {
+        let gun1 = Gun::new(AbsoluteDirection::XPlus, arena);
+        let gun2 = Gun::new(AbsoluteDirection::XMinus, arena);
         Self { gun1, gun2 }
     }
 }
@@ -220,18 +276,14 @@ impl Guns {
 // Gun
 pub struct Gun {
     direction: AbsoluteDirection,
-    thread_rng: rand::rngs::ThreadRng,
+    arena: Arena,
 }
 impl Gun {
-    pub fn new(direction: AbsoluteDirection) -> Self {
-        let thread_rng = rand::thread_rng();
-        Self {
-            direction,
-            thread_rng,
-        }
+    pub fn new(direction: AbsoluteDirection, arena: Arena) -> Self {
+        Self { direction, arena }
     }
-    pub fn shoot(&mut self, player_x: i32) -> Bullet {
-        let y = self.thread_rng.gen_range(0..=64 + 6) - 3;
+    pub fn shoot(&mut self, rng: &mut StdRng, player_x: i32) -> Bullet {
+        let y = rng.gen_range(0..=self.arena.height + 6) - 3;
         Bullet::new(player_x, y, self.direction)
     }
 }
@@ -242,9 +294,9 @@ pub struct Lasers {
     pub laser2: Laser,
 }
 impl Lasers {
-    pub fn new() -> Self {
-        let laser1 = Laser::new(AbsoluteDirection::XPlus);
-        let laser2 = Laser::new(AbsoluteDirection::XMinus);
+    pub fn new(arena: Arena) -> Self {
+        let laser1 = Laser::new(AbsoluteDirection::XPlus, arena);
+        let laser2 = Laser::new(AbsoluteDirection::XMinus, arena);
         Self { laser1, laser2 }
     }
 }
@@ -252,29 +304,32 @@ impl Lasers {
 // Laser struct
 pub struct Laser {
     direction: AbsoluteDirection,
-    thread_rng: rand::rngs::ThreadRng,
     emittable: bool,
     remaining_tick: Option<i32>,
+    arena: Arena,
 }
 impl Laser {
-    pub fn new(direction: AbsoluteDirection) -> Self {
-        let thread_rng = rand::thread_rng();
+    pub fn new(direction: AbsoluteDirection, arena: Arena) -> Self {
         let emittable = false;
         let remaining_tick = None;
         Self {
             direction,
-            thread_rng,
             emittable,
             remaining_tick,
+            arena,
         }
     }
+    // Whether pressing the button now fires the laser
+    pub fn is_ready(&self) -> bool {
+        self.emittable
+    }
     pub fn try_emit(
         &mut self,
-        led: &mut Led,
+        rng: &mut StdRng,
         opponent_player_y: i32,
-        button_level: Level,
+        button_pressed: bool,
     ) -> Option<Bullet> {
-        match (self.remaining_tick, self.emittable, button_level) {
+        match (self.remaining_tick, self.emittable, button_pressed) {
             (Some(0), _, _) => {
                 self.remaining_tick = None;
             }
@@ -282,29 +337,26 @@ impl Laser {
                 self.remaining_tick = Some(t - 1);
                 return Some(self.emit(opponent_player_y));
             }
-            (None, true, Low) => {
-                self.remaining_tick = Some(EMIT_TICK_SIZE);
-                led.set_low();
+            (None, true, true) => {
+                self.remaining_tick = Some(config().laser.emit_ticks);
                 self.emittable = false;
                 self.emit(opponent_player_y);
             }
-            (None, false, _) => {
-                if self.thread_rng.gen_range(0..EMIT_PROBABILITY) == 0 {
-                    led.set_high();
-                    self.emittable = true;
-                }
+            (None, false, _) if rng.gen_range(0..config().laser.emit_probability) == 0 => {
+                self.emittable = true;
             }
             _ => (),
         }
         None
     }
     pub fn emit(&mut self, opponent_player_y: i32) -> Bullet {
+        let spawn_position = config().laser.spawn_position;
         match self.direction {
             AbsoluteDirection::XPlus => {
-                Bullet::new(-LASER_SPAWN_POSITION, opponent_player_y, self.direction)
+                Bullet::new(-spawn_position, opponent_player_y, self.direction)
             }
             AbsoluteDirection::XMinus => Bullet::new(
-                DISPLAY_SIZE_X - LASER_SPAWN_POSITION,
+                self.arena.width - spawn_position,
                 opponent_player_y,
                 self.direction,
             ),
@@ -313,39 +365,131 @@ impl Laser {
 }
 
 // Bullets struct
-pub type Bullets = Vec<Bullet>;
+// A fixed number of slots allocated once, free slots hold inactive bullets.
+// Shooting takes a free slot, so ticks do not allocate.
+#[derive(Clone)]
+pub struct Bullets {
+    slots: Vec<Bullet>,
+    // Indices of the free slots, the last one is taken next
+    free: Vec<usize>,
+    // When each slot was taken, to find the oldest bullet
+    serials: Vec<u64>,
+    next_serial: u64,
+    overflow: PoolOverflow,
+    // Most bullets in flight at once
+    peak: usize,
+    // Bullets dropped or replaced as every slot was taken
+    overflows: u64,
+}
+impl Bullets {
+    pub fn new(capacity: usize, overflow: PoolOverflow) -> Self {
+        let mut free = Bullet::new(0, 0, AbsoluteDirection::XPlus);
+        free.disable();
+        Self {
+            slots: vec![free; capacity],
+            free: (0..capacity).rev().collect(),
+            serials: vec![0; capacity],
+            next_serial: 0,
+            overflow,
+            peak: 0,
+            overflows: 0,
+        }
+    }
+
+    // Put `bullet` in a free slot, or follow the overflow policy when there is
+    // none
+    pub fn spawn(&mut self, bullet: Bullet) {
+        let slot = match self.free.pop() {
+            Some(slot) => slot,
+            None => {
+                self.overflows += 1;
+                // Only scanned when every slot is taken
+                let oldest = (0..self.slots.len()).min_by_key(|&slot| self.serials[slot]);
+                match (self.overflow, oldest) {
+                    (PoolOverflow::ReplaceOldest, Some(oldest)) => oldest,
+                    _ => return,
+                }
+            }
+        };
+        self.slots[slot] = bullet;
+        self.serials[slot] = self.next_serial;
+        self.next_serial += 1;
+        self.peak = self.peak.max(self.in_flight());
+    }
+
+    // Free the slot of the bullet at `slot` if it is active
+    pub fn release(&mut self, slot: usize) {
+        if self.slots[slot].active {
+            self.slots[slot].disable();
+            self.free.push(slot);
+        }
+    }
+
+    // Free the slots of the bullets more than `margin` outside `arena`
+    pub fn release_outside(&mut self, arena: &Arena, margin: i32) {
+        for slot in 0..self.slots.len() {
+            if !arena.contains(&self.slots[slot].get_position(), margin) {
+                self.release(slot);
+            }
+        }
+    }
+
+    // All slots, the free ones are inactive. Bullets are freed by `release`,
+    // which keeps the count of free slots.
+    pub fn slots(&self) -> &[Bullet] {
+        &self.slots
+    }
+    pub fn slots_mut(&mut self) -> &mut [Bullet] {
+        &mut self.slots
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+    pub fn peak(&self) -> usize {
+        self.peak
+    }
+    pub fn overflows(&self) -> u64 {
+        self.overflows
+    }
+}
 
 // Bullet struct
+#[derive(Clone)]
 pub struct Bullet {
     x: i32,
     y: i32,
+    // Position before the last tick
+    last_position: Position,
     direction: AbsoluteDirection,
-    speed: i32,
+    // Pixels per tick
+    velocity: Point,
     pub active: bool,
 }
 impl Bullet {
-    fn new(x: i32, y: i32, direction: AbsoluteDirection) -> Self {
-        let speed = 1;
+    pub fn new(x: i32, y: i32, direction: AbsoluteDirection) -> Self {
+        let speed = config().shoot.bullet_speed;
+        let velocity = match direction {
+            AbsoluteDirection::XPlus => Point::new(speed, 0),
+            AbsoluteDirection::XMinus => Point::new(-speed, 0),
+        };
         let active = true;
         Self {
             x,
             y,
+            last_position: Position::new(x, y),
             direction,
-            speed,
+            velocity,
             active,
         }
     }
 
     pub fn tick(&mut self) {
-        match self.direction {
-            AbsoluteDirection::XPlus => self.transfer(self.speed, 0),
-            AbsoluteDirection::XMinus => self.transfer(-self.speed, 0),
-        }
-        if self.direction == AbsoluteDirection::XPlus {
-            self.transfer(self.speed, 0);
-        } else if self.direction == AbsoluteDirection::XMinus {
-            self.transfer(-self.speed, 0);
-        }
+        self.last_position = self.get_position();
+        self.transfer(self.velocity.x, self.velocity.y);
     }
 
     fn transfer(&mut self, x: i32, y: i32) {
@@ -353,37 +497,132 @@ impl Bullet {
         self.y += y;
     }
 
-    pub fn draw(&mut self, display: &mut Display) {
+    pub fn draw<D: DrawTarget<Color = BinaryColor>>(
+        &self,
+        display: &mut D,
+    ) -> Result<(), D::Error> {
         let style = PrimitiveStyleBuilder::new()
             .fill_color(BinaryColor::On)
             .build();
         match self.direction {
-            AbsoluteDirection::XPlus => {
-                Triangle::new(
-                    Point::new(self.x + 2, self.y),
-                    Point::new(self.x - 2, self.y + 1),
-                    Point::new(self.x - 2, self.y - 1),
-                )
-                .into_styled(style)
-                .draw(display)
-                .unwrap();
-            }
-            AbsoluteDirection::XMinus => {
-                Triangle::new(
-                    Point::new(self.x - 2, self.y),
-                    Point::new(self.x + 2, self.y + 1),
-                    Point::new(self.x + 2, self.y - 1),
-                )
-                .into_styled(style)
-                .draw(display)
-                .unwrap();
-            }
+            AbsoluteDirection::XPlus => Triangle::new(
+                Point::new(self.x + 2, self.y),
+                Point::new(self.x - 2, self.y + 1),
+                Point::new(self.x - 2, self.y - 1),
+            )
+            .into_styled(style)
+            .draw(display),
+            AbsoluteDirection::XMinus => Triangle::new(
+                Point::new(self.x - 2, self.y),
+                Point::new(self.x + 2, self.y + 1),
+                Point::new(self.x + 2, self.y - 1),
+            )
+            .into_styled(style)
+            .draw(display),
         }
     }
     pub fn get_position(&self) -> Position {
         Position::new(self.x, self.y)
     }
-    pub fn disable(&mut self) {
+    pub fn get_motion(&self) -> Motion {
+        Motion {
+            from: self.last_position,
+            to: self.get_position(),
+        }
+    }
+    fn disable(&mut self) {
         self.active = false;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bullet(x: i32) -> Bullet {
+        Bullet::new(x, 0, AbsoluteDirection::XPlus)
+    }
+
+    // x of the bullets in flight, ascending
+    fn in_flight(bullets: &Bullets) -> Vec<i32> {
+        let mut xs: Vec<i32> = bullets
+            .slots()
+            .iter()
+            .filter(|bullet| bullet.active)
+            .map(|bullet| bullet.get_position().x)
+            .collect();
+        xs.sort();
+        xs
+    }
+
+    #[test]
+    fn replace_oldest() {
+        let mut one = Bullets::new(1, PoolOverflow::ReplaceOldest);
+        one.spawn(bullet(1));
+        one.spawn(bullet(2));
+        assert_eq!(in_flight(&one), [2]);
+        assert_eq!((one.overflows(), one.peak()), (1, 1));
+
+        let mut two = Bullets::new(2, PoolOverflow::ReplaceOldest);
+        for x in 1..=3 {
+            two.spawn(bullet(x));
+        }
+        assert_eq!(in_flight(&two), [2, 3]);
+        assert_eq!((two.overflows(), two.peak()), (1, 2));
+        // A freed slot is taken before the oldest bullet is replaced
+        let slot = two.slots().iter().position(|b| b.get_position().x == 3);
+        two.release(slot.unwrap());
+        two.spawn(bullet(4));
+        assert_eq!(in_flight(&two), [2, 4]);
+        assert_eq!(two.overflows(), 1);
+        two.spawn(bullet(5));
+        assert_eq!(in_flight(&two), [4, 5]);
+        assert_eq!((two.overflows(), two.peak()), (2, 2));
+    }
+
+    #[test]
+    fn drop_new() {
+        let mut one = Bullets::new(1, PoolOverflow::DropNew);
+        one.spawn(bullet(1));
+        one.spawn(bullet(2));
+        assert_eq!(in_flight(&one), [1]);
+        assert_eq!((one.overflows(), one.peak()), (1, 1));
+
+        let mut two = Bullets::new(2, PoolOverflow::DropNew);
+        for x in 1..=3 {
+            two.spawn(bullet(x));
+        }
+        assert_eq!(in_flight(&two), [1, 2]);
+        assert_eq!((two.overflows(), two.peak()), (1, 2));
+        let slot = two.slots().iter().position(|b| b.get_position().x == 1);
+        two.release(slot.unwrap());
+        two.spawn(bullet(4));
+        two.spawn(bullet(5));
+        assert_eq!(in_flight(&two), [2, 4]);
+        assert_eq!((two.overflows(), two.peak()), (2, 2));
+    }
+
+    #[test]
+    fn peak_and_release() {
+        let arena = Arena::new(Size::new(128, 64));
+        let mut bullets = Bullets::new(2, PoolOverflow::DropNew);
+        bullets.spawn(bullet(10));
+        bullets.spawn(bullet(200));
+        assert_eq!((bullets.in_flight(), bullets.peak()), (2, 2));
+        bullets.release_outside(&arena, 8);
+        assert_eq!(in_flight(&bullets), [10]);
+        // Releasing a free slot again does not free it twice
+        let slot = bullets.slots().iter().position(|b| !b.active);
+        bullets.release(slot.unwrap());
+        assert_eq!(bullets.in_flight(), 1);
+        bullets.spawn(bullet(20));
+        bullets.spawn(bullet(30));
+        assert_eq!(in_flight(&bullets), [10, 20]);
+        assert_eq!((bullets.peak(), bullets.overflows()), (2, 1));
+        for slot in 0..bullets.capacity() {
+            bullets.release(slot);
+        }
+        bullets.spawn(bullet(40));
+        assert_eq!((bullets.in_flight(), bullets.peak()), (1, 2));
+    }
+}
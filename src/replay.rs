@@ -0,0 +1,426 @@
+// Replay files. A match is decided by its seed, the arena, the key bindings,
+// the gameplay settings and the inputs of every tick, so that is all a replay
+// keeps. Inputs are written on the ticks they change:
+//
+//   vshooter-replay 2
+//   seed 42
+//   arena 128 64
+//   player1 R C D F        keys of up, down, left and right
+//   player2 I M J K
+//   config hit.distance 0  a gameplay setting, see `gameplay`
+//   12 - D                 tick, laser buttons pressed and keys held from then
+//   30 1 D,R
+//   31 - D,R
+//   end 2071 -1 7 di       tick, hitpoints and winner when the match ended
+use crate::config::{config, HitShape, PoolOverflow};
+use crate::game::{GameState, Inputs};
+use crate::interface::Keycodes;
+use crate::key_binding::{KeyBindings, ScreenKeys};
+use crate::object::{Arena, Team};
+use anyhow::{bail, ensure, Context};
+use device_query::Keycode;
+use std::{fmt::Write, fs, path::Path};
+
+const HEADER: &str = "vshooter-replay 2";
+
+// How a match ended
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Outcome {
+    pub tick: u128,
+    pub mono_hitpoint: i8,
+    pub di_hitpoint: i8,
+    pub winner: Option<Team>,
+}
+impl Outcome {
+    pub fn new(state: &GameState) -> Self {
+        Self {
+            tick: state.tick,
+            mono_hitpoint: state.mono_hitpoint,
+            di_hitpoint: state.di_hitpoint,
+            winner: state.winner,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Replay {
+    pub seed: u64,
+    pub arena: Arena,
+    pub key_bindings: KeyBindings,
+    // `gameplay` when the match was played
+    settings: Vec<(String, String)>,
+    // Inputs from their tick until the next change
+    changes: Vec<(u128, Inputs)>,
+    pub outcome: Option<Outcome>,
+}
+impl Replay {
+    pub fn new(seed: u64, arena: Arena, key_bindings: &KeyBindings) -> Self {
+        Self {
+            seed,
+            arena,
+            key_bindings: key_bindings.clone(),
+            settings: gameplay(),
+            changes: Vec::new(),
+            outcome: None,
+        }
+    }
+
+    // Record the inputs of `tick`, only keys bound to a direction are kept
+    pub fn record(&mut self, tick: u128, inputs: &Inputs) {
+        let bound: Vec<&Keycode> = self
+            .key_bindings
+            .players()
+            .into_iter()
+            .flat_map(|keys| keys.directions())
+            .flatten()
+            .collect();
+        let inputs = Inputs {
+            keycodes: inputs
+                .keycodes
+                .iter()
+                .filter(|keycode| bound.contains(keycode))
+                .copied()
+                .collect(),
+            ..inputs.clone()
+        };
+        if self.inputs(tick) != inputs {
+            self.changes.push((tick, inputs));
+        }
+    }
+
+    pub fn finish(&mut self, state: &GameState) {
+        self.outcome = Some(Outcome::new(state));
+    }
+
+    // Inputs of `tick`
+    pub fn inputs(&self, tick: u128) -> Inputs {
+        let next = self.changes.partition_point(|(from, _)| *from <= tick);
+        match next.checked_sub(1) {
+            Some(i) => self.changes[i].1.clone(),
+            None => Inputs::default(),
+        }
+    }
+
+    // Whether `state` ended as the recorded match did
+    pub fn check(&self, state: &GameState) -> anyhow::Result<()> {
+        let replayed = Outcome::new(state);
+        match self.outcome {
+            Some(recorded) if recorded != replayed => bail!(
+                "replay diverged, recorded {recorded:?} but replayed {replayed:?} \
+                 (is the config the same?)"
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    // A replay only plays as it was recorded with the same gameplay settings
+    pub fn check_config(&self) -> anyhow::Result<()> {
+        let current = gameplay();
+        let differences: Vec<String> = current
+            .iter()
+            .filter(|setting| !self.settings.contains(setting))
+            .map(|(name, value)| {
+                let recorded = self
+                    .settings
+                    .iter()
+                    .find(|(recorded, _)| recorded == name)
+                    .map_or("unset", |(_, value)| value);
+                format!("{name} {recorded} (now {value})")
+            })
+            .collect();
+        ensure!(
+            differences.is_empty() && self.settings.len() == current.len(),
+            "the replay was recorded with other settings: {}",
+            differences.join(", ")
+        );
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let mut text = String::new();
+        writeln!(text, "{HEADER}")?;
+        writeln!(text, "seed {}", self.seed)?;
+        writeln!(text, "arena {} {}", self.arena.width, self.arena.height)?;
+        for (player, keys) in self.key_bindings.players().into_iter().enumerate() {
+            let directions: Vec<String> = keys.directions().into_iter().map(key_list).collect();
+            writeln!(text, "player{} {}", player + 1, directions.join(" "))?;
+        }
+        for (name, value) in &self.settings {
+            writeln!(text, "config {name} {value}")?;
+        }
+        for (tick, inputs) in &self.changes {
+            let lasers = match (inputs.laser1, inputs.laser2) {
+                (false, false) => "-",
+                (true, false) => "1",
+                (false, true) => "2",
+                (true, true) => "12",
+            };
+            writeln!(text, "{tick} {lasers} {}", key_list(&inputs.keycodes))?;
+        }
+        if let Some(outcome) = self.outcome {
+            let winner = match outcome.winner {
+                Some(Team::Mono) => "mono",
+                Some(Team::Di) => "di",
+                None => "-",
+            };
+            writeln!(
+                text,
+                "end {} {} {} {winner}",
+                outcome.tick, outcome.mono_hitpoint, outcome.di_hitpoint
+            )?;
+        }
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text).with_context(|| format!("invalid replay {}", path.display()))
+    }
+
+    fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, line));
+        let mut next_line = |what: &str| {
+            lines
+                .next()
+                .map(|(number, line)| (number, line.split_whitespace().collect::<Vec<_>>()))
+                .with_context(|| format!("missing {what}"))
+        };
+
+        let (_, header) = next_line("header")?;
+        ensure!(header.join(" ") == HEADER, "expected `{HEADER}`");
+        let (number, fields) = next_line("seed")?;
+        let ["seed", seed] = fields[..] else {
+            bail!("line {number}: expected `seed <n>`");
+        };
+        let seed = seed.parse().with_context(|| format!("line {number}"))?;
+        let (number, fields) = next_line("arena")?;
+        let ["arena", width, height] = fields[..] else {
+            bail!("line {number}: expected `arena <width> <height>`");
+        };
+        let arena = Arena {
+            width: width.parse().with_context(|| format!("line {number}"))?,
+            height: height.parse().with_context(|| format!("line {number}"))?,
+        };
+        let mut players = Vec::new();
+        for name in ["player1", "player2"] {
+            let (number, fields) = next_line(name)?;
+            let [player, up, down, left, right] = fields[..] else {
+                bail!("line {number}: expected `{name} <up> <down> <left> <right>`");
+            };
+            ensure!(player == name, "line {number}: expected `{name}`");
+            players.push(ScreenKeys {
+                up: parse_key_list(up).with_context(|| format!("line {number}"))?,
+                down: parse_key_list(down).with_context(|| format!("line {number}"))?,
+                left: parse_key_list(left).with_context(|| format!("line {number}"))?,
+                right: parse_key_list(right).with_context(|| format!("line {number}"))?,
+            });
+        }
+        let [player1, player2] = <[ScreenKeys; 2]>::try_from(players).unwrap();
+        let mut replay = Self::new(seed, arena, &KeyBindings { player1, player2 });
+        replay.settings.clear();
+
+        while let Ok((number, fields)) = next_line("end") {
+            if let ["end", tick, mono_hitpoint, di_hitpoint, winner] = fields[..] {
+                replay.outcome = Some(Outcome {
+                    tick: tick.parse().with_context(|| format!("line {number}"))?,
+                    mono_hitpoint: mono_hitpoint
+                        .parse()
+                        .with_context(|| format!("line {number}"))?,
+                    di_hitpoint: di_hitpoint
+                        .parse()
+                        .with_context(|| format!("line {number}"))?,
+                    winner: match winner {
+                        "mono" => Some(Team::Mono),
+                        "di" => Some(Team::Di),
+                        "-" => None,
+                        _ => bail!("line {number}: unknown winner `{winner}`"),
+                    },
+                });
+                break;
+            }
+            if let ["config", name, value] = fields[..] {
+                ensure!(
+                    replay.changes.is_empty(),
+                    "line {number}: settings must come before the inputs"
+                );
+                replay.settings.push((name.to_string(), value.to_string()));
+                continue;
+            }
+            let [tick, lasers, keys] = fields[..] else {
+                bail!("line {number}: expected `<tick> <lasers> <keys>`");
+            };
+            let tick: u128 = tick.parse().with_context(|| format!("line {number}"))?;
+            ensure!(
+                replay.changes.last().is_none_or(|(last, _)| *last < tick),
+                "line {number}: ticks must increase"
+            );
+            let (laser1, laser2) = match lasers {
+                "-" => (false, false),
+                "1" => (true, false),
+                "2" => (false, true),
+                "12" => (true, true),
+                _ => bail!("line {number}: expected `-`, `1`, `2` or `12` for the lasers"),
+            };
+            replay.changes.push((
+                tick,
+                Inputs {
+                    keycodes: parse_key_list(keys).with_context(|| format!("line {number}"))?,
+                    laser1,
+                    laser2,
+                },
+            ));
+        }
+        Ok(replay)
+    }
+}
+
+// Settings of `config()` which change how a match plays, as they are written
+// in the config file
+fn gameplay() -> Vec<(String, String)> {
+    let config = config();
+    let shape = |shape| match shape {
+        HitShape::Point => "point",
+        HitShape::Box => "box",
+        HitShape::Mask => "mask",
+    };
+    let overflow = match config.shoot.pool_overflow {
+        PoolOverflow::ReplaceOldest => "replace-oldest",
+        PoolOverflow::DropNew => "drop-new",
+    };
+    [
+        (
+            "tick.clean_interval",
+            config.tick.clean_interval.to_string(),
+        ),
+        ("tick.result_ticks", config.tick.result_ticks.to_string()),
+        ("hitpoint.initial", config.hitpoint.initial.to_string()),
+        (
+            "hitpoint.bullet_damage",
+            config.hitpoint.bullet_damage.to_string(),
+        ),
+        ("hit.distance", config.hit.distance.to_string()),
+        ("hit.display_margin", config.hit.display_margin.to_string()),
+        ("hit.player", shape(config.hit.player).to_string()),
+        ("hit.bullet", shape(config.hit.bullet).to_string()),
+        ("shoot.interval", config.shoot.interval.to_string()),
+        ("shoot.bullet_speed", config.shoot.bullet_speed.to_string()),
+        (
+            "shoot.pool_capacity",
+            config.shoot.pool_capacity.to_string(),
+        ),
+        ("shoot.pool_overflow", overflow.to_string()),
+        ("laser.emit_ticks", config.laser.emit_ticks.to_string()),
+        (
+            "laser.spawn_position",
+            config.laser.spawn_position.to_string(),
+        ),
+        (
+            "laser.emit_probability",
+            config.laser.emit_probability.to_string(),
+        ),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value))
+    .collect()
+}
+
+// `F,Up`, or `-` without keys
+fn key_list(keycodes: &Keycodes) -> String {
+    if keycodes.is_empty() {
+        return "-".to_string();
+    }
+    keycodes
+        .iter()
+        .map(|keycode| keycode.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn parse_key_list(text: &str) -> anyhow::Result<Keycodes> {
+    if text == "-" {
+        return Ok(Vec::new());
+    }
+    text.split(',')
+        .map(|name| {
+            name.parse()
+                .map_err(|_| anyhow::anyhow!("unknown key `{name}`"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics::prelude::Size;
+    use std::{env, process};
+
+    #[test]
+    fn save_and_load() {
+        let key_bindings = KeyBindings {
+            player1: ScreenKeys {
+                up: vec![Keycode::R, Keycode::Up],
+                down: vec![Keycode::C],
+                left: vec![Keycode::D],
+                right: vec![Keycode::F],
+            },
+            player2: ScreenKeys {
+                up: vec![Keycode::Numpad8],
+                down: vec![Keycode::Numpad2],
+                left: vec![Keycode::Numpad4],
+                right: vec![Keycode::Numpad6],
+            },
+        };
+        let mut replay = Replay::new(42, Arena::new(Size::new(128, 32)), &key_bindings);
+        let inputs = |keycodes: &[Keycode], laser1, laser2| Inputs {
+            keycodes: keycodes.to_vec(),
+            laser1,
+            laser2,
+        };
+        replay.record(1, &inputs(&[], false, false));
+        replay.record(12, &inputs(&[Keycode::D], false, false));
+        replay.record(30, &inputs(&[Keycode::D, Keycode::Up], true, false));
+        replay.record(31, &inputs(&[Keycode::Numpad8], false, true));
+        replay.record(32, &inputs(&[Keycode::Numpad8], true, true));
+        // Unbound keys are not kept
+        replay.record(40, &inputs(&[Keycode::Z], false, false));
+        replay.outcome = Some(Outcome {
+            tick: 2071,
+            mono_hitpoint: -1,
+            di_hitpoint: 7,
+            winner: Some(Team::Di),
+        });
+
+        let path = env::temp_dir().join(format!("vshooter-test-{}.replay", process::id()));
+        replay.save(&path).unwrap();
+        let loaded = Replay::load(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.seed, replay.seed);
+        assert_eq!(loaded.arena, replay.arena);
+        assert_eq!(loaded.key_bindings, replay.key_bindings);
+        assert_eq!(loaded.settings, replay.settings);
+        loaded.check_config().unwrap();
+        assert_eq!(loaded.changes, replay.changes);
+        assert_eq!(loaded.outcome, replay.outcome);
+        assert_eq!(loaded.inputs(40), Inputs::default());
+    }
+
+    #[test]
+    fn other_settings_refused() {
+        let arena = Arena::new(Size::new(128, 64));
+        let mut replay = Replay::new(1, arena, &config().key_bindings());
+        replay.check_config().unwrap();
+        let distance = replay
+            .settings
+            .iter_mut()
+            .find(|(name, _)| name == "hit.distance")
+            .unwrap();
+        distance.1 = (config().hit.distance + 1).to_string();
+        let error = replay.check_config().unwrap_err().to_string();
+        assert!(error.contains("hit.distance"), "{error}");
+        // A replay which does not have a setting is refused too
+        replay.settings.retain(|(name, _)| name != "hit.distance");
+        assert!(replay.check_config().is_err());
+    }
+}
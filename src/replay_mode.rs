@@ -0,0 +1,487 @@
+use crate::game::{render, GameEvent, GameState};
+use crate::interface::{led_pattern::Pattern, Display, Interfaces};
+use crate::replay::Replay;
+use crate::scene::{Input, Press, Presses, Scene, Session, Transition};
+use crate::shooting_mode::{check_arena, show_leds, sound_effect};
+use device_query::Keycode;
+use display_interface::DisplayError;
+use embedded_graphics::{
+    mono_font::{ascii::FONT_4X6, MonoTextStyle},
+    pixelcolor::BinaryColor,
+    prelude::*,
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+};
+
+// Speeds in eighths of a tick per frame, from 1/8x to 8x
+const SPEEDS: [u32; 7] = [1, 2, 4, 8, 16, 32, 64];
+const NORMAL_SPEED: usize = 3;
+// How far a long press of button 1 or Page Up/Down seeks
+const SEEK_TICKS: u128 = 1000;
+
+// Replay viewer scene
+// Space or button 1 pauses and plays. While paused, Left/Right or button 2 step
+// one tick, while playing Up/Down or button 2 change the speed. Page Up/Down
+// and a long press of button 1 seek back and forth, Home and End jump to the
+// ends, and a tick typed in digits is sought with Enter. A long press of button
+// 2 or Escape leaves.
+pub struct ViewerScene {
+    viewer: Viewer,
+    presses: Presses,
+    paused: bool,
+    speed: usize,
+    // Eighths of a tick to advance
+    budget: u32,
+    // Tick typed so far
+    typed: Option<String>,
+}
+impl ViewerScene {
+    pub fn new(replay: &Replay) -> Self {
+        Self {
+            viewer: Viewer::new(replay.clone()),
+            presses: Presses::new(),
+            paused: false,
+            speed: NORMAL_SPEED,
+            budget: 0,
+            typed: None,
+        }
+    }
+}
+
+impl<D: Display> Scene<D> for ViewerScene {
+    fn enter(
+        &mut self,
+        interfaces: &mut Interfaces<D>,
+        _session: &mut Session,
+    ) -> anyhow::Result<()> {
+        check_arena(&self.viewer.replay, interfaces.display.size())?;
+        self.viewer.replay.check_config()
+    }
+
+    fn update(
+        &mut self,
+        interfaces: &mut Interfaces<D>,
+        _session: &mut Session,
+        input: &Input,
+    ) -> anyhow::Result<Transition> {
+        let [button1, button2] = self.presses.read(&input.button_events);
+        let pressed = &input.pressed;
+        let viewer = &mut self.viewer;
+
+        // Process
+        let tick = viewer.state.tick;
+        if let Some(digits) = &mut self.typed {
+            for keycode in pressed {
+                match keycode {
+                    Keycode::Backspace => {
+                        digits.pop();
+                    }
+                    Keycode::Enter => {
+                        // Only digits are typed, so a number which does not
+                        // parse is too large for a tick and past the end
+                        if !digits.is_empty() {
+                            viewer.seek(digits.parse().unwrap_or(u128::MAX));
+                            self.paused = true;
+                        }
+                        self.typed = None;
+                        break;
+                    }
+                    Keycode::Escape => {
+                        self.typed = None;
+                        break;
+                    }
+                    keycode => digits.extend(digit(*keycode)),
+                }
+            }
+        } else if let Some(first) = pressed.iter().find_map(|keycode| digit(*keycode)) {
+            self.typed = Some(first.to_string());
+        } else if button2 == Press::Long || pressed.contains(&Keycode::Escape) {
+            return Ok(Transition::Quit);
+        } else if button1 == Press::Short || pressed.contains(&Keycode::Space) {
+            self.paused = !self.paused;
+        } else if button1 == Press::Long || pressed.contains(&Keycode::PageUp) {
+            viewer.seek(tick.saturating_sub(SEEK_TICKS));
+        } else if pressed.contains(&Keycode::PageDown) {
+            viewer.seek(tick + SEEK_TICKS);
+        } else if pressed.contains(&Keycode::Home) {
+            viewer.seek(0);
+        } else if pressed.contains(&Keycode::End) {
+            viewer.seek(u128::MAX);
+        } else if pressed.contains(&Keycode::Left) {
+            self.paused = true;
+            viewer.seek(tick.saturating_sub(1));
+        } else if pressed.contains(&Keycode::Right) || (self.paused && button2 == Press::Short) {
+            self.paused = true;
+            viewer.seek(tick + 1);
+        } else if pressed.contains(&Keycode::Up) {
+            self.speed = (self.speed + 1).min(SPEEDS.len() - 1);
+        } else if pressed.contains(&Keycode::Down) {
+            self.speed = self.speed.saturating_sub(1);
+        } else if button2 == Press::Short {
+            self.speed = (self.speed + 1) % SPEEDS.len();
+        }
+
+        // Tick
+        if self.paused || viewer.state.is_over() {
+            self.budget = 0;
+        } else {
+            self.budget += SPEEDS[self.speed];
+            while self.budget >= SPEEDS[NORMAL_SPEED] && !viewer.state.is_over() {
+                self.budget -= SPEEDS[NORMAL_SPEED];
+                let events = viewer.step();
+                // sounds only make sense at normal speed
+                if self.speed == NORMAL_SPEED {
+                    for effect in events.into_iter().filter_map(sound_effect) {
+                        interfaces.sound.play(effect);
+                    }
+                }
+            }
+        }
+
+        // Leds
+        show_leds(interfaces, &viewer.state);
+        Ok(Transition::Stay)
+    }
+
+    fn render(&self, _session: &Session, display: &mut D) -> Result<(), DisplayError> {
+        let state = &self.viewer.state;
+        render(state, display)?;
+
+        // Draw where and how fast the replay is, between the results
+        let status = match &self.typed {
+            Some(digits) => format!("seek {digits}_"),
+            None if self.paused => format!("|| {}", state.tick),
+            None => format!("> {} {}", state.tick, speed_name(SPEEDS[self.speed])),
+        };
+        Text::with_text_style(
+            &status,
+            Point::new(state.arena.width / 2, 2),
+            MonoTextStyle::new(&FONT_4X6, BinaryColor::On),
+            TextStyleBuilder::new()
+                .alignment(Alignment::Center)
+                .baseline(Baseline::Top)
+                .build(),
+        )
+        .draw(display)?;
+        Ok(())
+    }
+
+    fn exit(
+        &mut self,
+        interfaces: &mut Interfaces<D>,
+        _session: &mut Session,
+    ) -> anyhow::Result<()> {
+        interfaces.leds.led1.set(Pattern::Off);
+        interfaces.leds.led2.set(Pattern::Off);
+        interfaces.leds.tick();
+        interfaces.sound.stop();
+        if self.viewer.state.is_over() {
+            self.viewer.replay.check(&self.viewer.state)?;
+        }
+        Ok(())
+    }
+}
+
+// The match of a replay at any tick
+struct Viewer {
+    replay: Replay,
+    state: GameState,
+}
+impl Viewer {
+    fn new(replay: Replay) -> Self {
+        Self {
+            state: Self::start(&replay),
+            replay,
+        }
+    }
+
+    fn start(replay: &Replay) -> GameState {
+        GameState::new(&replay.key_bindings, replay.arena, replay.seed)
+    }
+
+    fn step(&mut self) -> Vec<GameEvent> {
+        let inputs = self.replay.inputs(self.state.tick + 1);
+        self.state.step(&inputs)
+    }
+
+    // Go to `tick`, or the end of the match if it is over before. Going back
+    // simulates the match again from the start, which takes no time as
+    // nothing is drawn meanwhile.
+    fn seek(&mut self, tick: u128) {
+        if tick < self.state.tick {
+            self.state = Self::start(&self.replay);
+        }
+        while self.state.tick < tick && !self.state.is_over() {
+            self.step();
+        }
+    }
+}
+
+// Digit of a number key
+fn digit(keycode: Keycode) -> Option<char> {
+    use Keycode::*;
+    let value = match keycode {
+        Key0 | Numpad0 => 0,
+        Key1 | Numpad1 => 1,
+        Key2 | Numpad2 => 2,
+        Key3 | Numpad3 => 3,
+        Key4 | Numpad4 => 4,
+        Key5 | Numpad5 => 5,
+        Key6 | Numpad6 => 6,
+        Key7 | Numpad7 => 7,
+        Key8 | Numpad8 => 8,
+        Key9 | Numpad9 => 9,
+        _ => return None,
+    };
+    char::from_digit(value, 10)
+}
+
+// `1/4x` or `2x`
+fn speed_name(eighths: u32) -> String {
+    if eighths >= 8 {
+        format!("{}x", eighths / 8)
+    } else {
+        format!("1/{}x", 8 / eighths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config;
+    use crate::game::Inputs;
+    use crate::interface::{
+        debounce::{Button, ButtonEvent, ButtonEventKind, ButtonEvents},
+        frame_buffer::FrameBuffer,
+        headless::HeadlessDisplay,
+        mock::{ScriptedButtons, TickCounter},
+        null::{NoBuzzer, NoKeys, NoLed},
+        Leds,
+    };
+    use crate::object::Arena;
+    use crate::replay::Outcome;
+    use std::time::Duration;
+
+    const SEED: u64 = 3;
+
+    // A match with both players moving and firing their lasers
+    fn recorded() -> Replay {
+        let key_bindings = config().key_bindings();
+        let arena = Arena::new(config().display.size.size());
+        let mut replay = Replay::new(SEED, arena, &key_bindings);
+        let mut state = GameState::new(&key_bindings, arena, SEED);
+        while !state.is_over() {
+            let tick = state.tick + 1;
+            let inputs = Inputs {
+                keycodes: if tick % 200 < 120 {
+                    vec![Keycode::R, Keycode::M]
+                } else {
+                    vec![Keycode::C, Keycode::I]
+                },
+                laser1: tick.is_multiple_of(97),
+                laser2: tick.is_multiple_of(89),
+            };
+            replay.record(tick, &inputs);
+            state.step(&inputs);
+        }
+        replay.finish(&state);
+        replay
+    }
+
+    // What can be told of a state from outside: how the match stands and
+    // what is drawn
+    fn snapshot(state: &GameState) -> (Outcome, Vec<bool>) {
+        let mut frame = FrameBuffer::new(config().display.size.size());
+        render(state, &mut frame).unwrap();
+        (Outcome::new(state), frame.pixels().to_vec())
+    }
+
+    // Snapshot of every tick of the match, stepped one at a time
+    fn stepped(replay: &Replay) -> Vec<(Outcome, Vec<bool>)> {
+        let mut viewer = Viewer::new(replay.clone());
+        let mut snapshots = vec![snapshot(&viewer.state)];
+        while !viewer.state.is_over() {
+            viewer.step();
+            snapshots.push(snapshot(&viewer.state));
+        }
+        snapshots
+    }
+
+    #[test]
+    fn seek_as_stepping() {
+        let replay = recorded();
+        let snapshots = stepped(&replay);
+        let end = snapshots.len() as u128 - 1;
+        assert!(end > 1000, "{end}");
+        let mut viewer = Viewer::new(replay);
+        for tick in [500, 501, 100, 0, 1000, 999, end - 1, 1, end] {
+            viewer.seek(tick);
+            assert_eq!(viewer.state.tick, tick);
+            assert!(
+                snapshot(&viewer.state) == snapshots[tick as usize],
+                "{tick}"
+            );
+            // and goes on as it did
+            if tick < end {
+                viewer.step();
+                assert!(
+                    snapshot(&viewer.state) == snapshots[tick as usize + 1],
+                    "{tick}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn seek_past_the_end() {
+        let replay = recorded();
+        let end = replay.outcome.unwrap().tick;
+        let mut viewer = Viewer::new(replay);
+        for tick in [end + 1, u128::MAX, end + SEEK_TICKS] {
+            viewer.seek(tick);
+            assert!(viewer.state.is_over());
+            assert_eq!(viewer.state.tick, end);
+        }
+        viewer.replay.check(&viewer.state).unwrap();
+    }
+
+    fn interfaces() -> Interfaces<HeadlessDisplay> {
+        Interfaces::new(
+            HeadlessDisplay::new(config().display.size.size(), None),
+            Box::new(ScriptedButtons::new(TickCounter::new(), Vec::new())),
+            Leds::new(Box::new(NoLed), Box::new(NoLed)),
+            Box::new(NoKeys),
+            Box::new(NoBuzzer),
+        )
+    }
+
+    // A viewer scene updated one tick at a time
+    struct View {
+        scene: ViewerScene,
+        interfaces: Interfaces<HeadlessDisplay>,
+        session: Session,
+    }
+    impl View {
+        fn new(replay: &Replay) -> Self {
+            Self {
+                scene: ViewerScene::new(replay),
+                interfaces: interfaces(),
+                session: Session::new(config().key_bindings(), None, None, None),
+            }
+        }
+
+        fn update(&mut self, pressed: &[Keycode], button_events: ButtonEvents) -> u128 {
+            let input = Input {
+                keycodes: pressed.to_vec(),
+                pressed: pressed.to_vec(),
+                button_events,
+            };
+            let transition = self
+                .scene
+                .update(&mut self.interfaces, &mut self.session, &input)
+                .unwrap();
+            assert_eq!(transition, Transition::Stay);
+            self.scene.viewer.state.tick
+        }
+
+        fn press(&mut self, keycode: Keycode) -> u128 {
+            self.update(&[keycode], Vec::new())
+        }
+    }
+
+    fn click(button: Button) -> ButtonEvents {
+        [ButtonEventKind::Pressed, ButtonEventKind::Released]
+            .map(|kind| ButtonEvent {
+                button,
+                kind,
+                at: Duration::ZERO,
+            })
+            .to_vec()
+    }
+
+    #[test]
+    fn step_while_paused() {
+        let mut view = View::new(&recorded());
+        assert_eq!(view.update(&[], Vec::new()), 1);
+        assert_eq!(view.press(Keycode::Space), 1);
+        assert!(view.scene.paused);
+        assert_eq!(view.update(&[], Vec::new()), 1);
+        assert_eq!(view.press(Keycode::Right), 2);
+        assert_eq!(view.press(Keycode::Right), 3);
+        assert_eq!(view.press(Keycode::Left), 2);
+        assert_eq!(view.update(&[], click(Button::Button2)), 3);
+        assert_eq!(view.update(&[], Vec::new()), 3);
+        // Playing again
+        assert_eq!(view.update(&[], click(Button::Button1)), 4);
+        assert!(!view.scene.paused);
+        // Stepping pauses
+        assert_eq!(view.press(Keycode::Left), 3);
+        assert!(view.scene.paused);
+        assert_eq!(view.update(&[], Vec::new()), 3);
+    }
+
+    #[test]
+    fn speed_budget() {
+        let mut view = View::new(&recorded());
+        let updates = |view: &mut View, n| -> Vec<u128> {
+            (0..n).map(|_| view.update(&[], Vec::new())).collect()
+        };
+        // 1/8x, a tick every 8 updates
+        view.press(Keycode::Space);
+        for _ in 0..NORMAL_SPEED {
+            view.press(Keycode::Down);
+        }
+        assert_eq!(view.press(Keycode::Space), 0);
+        assert_eq!(
+            updates(&mut view, 15),
+            [0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 2]
+        );
+        // Pausing drops what is left of the budget
+        updates(&mut view, 4);
+        view.press(Keycode::Space);
+        assert_eq!(view.scene.budget, 0);
+        assert_eq!(view.press(Keycode::Space), 2);
+        assert_eq!(updates(&mut view, 7), [2, 2, 2, 2, 2, 2, 3]);
+        // 2x, two ticks per update
+        view.press(Keycode::Space);
+        for _ in 0..4 {
+            view.press(Keycode::Up);
+        }
+        assert_eq!(view.press(Keycode::Space), 5);
+        assert_eq!(updates(&mut view, 2), [7, 9]);
+        // 8x at most
+        for _ in 0..SPEEDS.len() {
+            view.press(Keycode::Up);
+        }
+        let tick = view.update(&[], Vec::new());
+        assert_eq!(updates(&mut view, 2), [tick + 8, tick + 16]);
+    }
+
+    #[test]
+    fn typed_tick() {
+        let replay = recorded();
+        let end = replay.outcome.unwrap().tick;
+        let mut view = View::new(&replay);
+        view.press(Keycode::Space);
+        for keycode in [
+            Keycode::Key1,
+            Keycode::Key2,
+            Keycode::Key3,
+            Keycode::Backspace,
+        ] {
+            view.press(keycode);
+        }
+        assert_eq!(view.scene.typed.as_deref(), Some("12"));
+        assert_eq!(view.press(Keycode::Enter), 12);
+        assert_eq!(view.scene.typed, None);
+        // Too large for a tick, the end of the match
+        for _ in 0..40 {
+            view.press(Keycode::Key9);
+        }
+        assert_eq!(view.press(Keycode::Enter), end);
+        // Nothing typed, nothing sought
+        view.press(Keycode::Home);
+        view.press(Keycode::Key5);
+        view.press(Keycode::Backspace);
+        assert_eq!(view.press(Keycode::Enter), 0);
+    }
+}
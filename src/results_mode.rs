@@ -0,0 +1,112 @@
+use crate::config::config;
+use crate::constant::RESULTS_TIMEOUT;
+use crate::interface::{Display, Interfaces};
+use crate::object::Team;
+use crate::scene::{fit_lines, Input, Press, Presses, Scene, SceneId, Session, Transition};
+use device_query::Keycode;
+use display_interface::DisplayError;
+use embedded_graphics::{
+    mono_font::{
+        ascii::{FONT_10X20, FONT_6X10},
+        MonoTextStyle,
+    },
+    pixelcolor::BinaryColor,
+    prelude::*,
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+};
+
+// Results scene, how the last match ended
+// Button 2 or Enter plays again and button 1 or Tab opens the menu, otherwise
+// it goes back to the title after a while, or leaves the game once all the
+// matches to play were played.
+pub struct ResultsScene {
+    presses: Presses,
+    ticks: u64,
+}
+impl ResultsScene {
+    pub fn new() -> Self {
+        Self {
+            presses: Presses::new(),
+            ticks: 0,
+        }
+    }
+}
+
+impl<D: Display> Scene<D> for ResultsScene {
+    fn update(
+        &mut self,
+        _interfaces: &mut Interfaces<D>,
+        session: &mut Session,
+        input: &Input,
+    ) -> anyhow::Result<Transition> {
+        let [button1, button2] = self.presses.read(&input.button_events);
+        self.ticks += 1;
+        Ok(
+            if button2 == Press::Short || input.pressed.contains(&Keycode::Enter) {
+                Transition::To(SceneId::Match)
+            } else if button1 == Press::Short || input.pressed.contains(&Keycode::Tab) {
+                Transition::To(SceneId::Menu)
+            } else if self.ticks >= config().tick.ticks(RESULTS_TIMEOUT) {
+                if session.is_done() {
+                    Transition::Quit
+                } else {
+                    Transition::To(SceneId::Title)
+                }
+            } else {
+                Transition::Stay
+            },
+        )
+    }
+
+    fn render(&self, session: &Session, display: &mut D) -> Result<(), DisplayError> {
+        let size = display.size();
+        let center_x = size.width as i32 / 2;
+        let center_style = TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Top)
+            .build();
+        display.clear(BinaryColor::Off)?;
+        let Some((seed, outcome)) = session
+            .last_match
+            .as_ref()
+            .and_then(|replay| Some((replay.seed, replay.outcome?)))
+        else {
+            return Ok(());
+        };
+        let winner = match outcome.winner {
+            Some(Team::Mono) => "P1 wins",
+            Some(Team::Di) => "P2 wins",
+            None => "No winner",
+        };
+        let lines = [
+            format!("HP {} - {}", outcome.mono_hitpoint, outcome.di_hitpoint),
+            format!("{} ticks  seed {}", outcome.tick, seed),
+            "B2 again  B1 menu".to_string(),
+        ];
+        // The winner in large letters if the lines still fit below
+        let title_font = if size.height >= 22 + lines.len() as u32 * 10 {
+            &FONT_10X20
+        } else {
+            &FONT_6X10
+        };
+        let top = title_font.character_size.height + 2;
+        Text::with_text_style(
+            winner,
+            Point::new(center_x, 0),
+            MonoTextStyle::new(title_font, BinaryColor::On),
+            center_style,
+        )
+        .draw(display)?;
+        let (small_style, row_height) = fit_lines(size.height - top, lines.len() as u32);
+        for (row, line) in lines.iter().enumerate() {
+            Text::with_text_style(
+                line,
+                Point::new(center_x, top as i32 + row as i32 * row_height),
+                small_style,
+                center_style,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,578 @@
+// Scenes. Every screen of the game is a scene and `run_scenes` runs one at a
+// time on the fixed timestep: on each tick the active scene is updated with the
+// inputs read and may switch to another scene, and it is drawn when a frame is
+// due. `enter` and `exit` run when a scene starts and when it is left.
+use crate::config::config;
+use crate::diagnostics_mode::DiagnosticsScene;
+use crate::interface::{
+    debounce::{Button, ButtonEventKind, ButtonEvents},
+    gif_recorder::GifRecorder,
+    Display, Interfaces, Keycodes,
+};
+use crate::key_binding::KeyBindings;
+use crate::key_binding_mode::KeyBindingScene;
+use crate::loading_mode::BootScene;
+use crate::menu_mode::MenuScene;
+use crate::object::Arena;
+use crate::replay::Replay;
+use crate::replay_mode::ViewerScene;
+use crate::results_mode::ResultsScene;
+use crate::shooting_mode::{MatchScene, ReplayScene};
+use crate::timestep::{Rates, Timestep};
+use crate::title_mode::TitleScene;
+use anyhow::{anyhow, Context};
+use display_interface::DisplayError;
+use embedded_graphics::{
+    mono_font::{
+        ascii::{FONT_4X6, FONT_6X10},
+        MonoTextStyle,
+    },
+    pixelcolor::BinaryColor,
+    prelude::*,
+};
+use std::path::PathBuf;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SceneId {
+    Boot,
+    Title,
+    Menu,
+    Match,
+    Results,
+    KeyBinding,
+    Diagnostics,
+    // Play `Session::replay` back
+    Replay,
+    // Open `Session::replay` in the viewer
+    Viewer,
+}
+
+// What to do after an update
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Transition {
+    Stay,
+    To(SceneId),
+    // Leave the game
+    Quit,
+}
+
+// What the scenes share
+pub struct Session {
+    pub key_bindings: KeyBindings,
+    // Seed of every match, a random one each without it
+    pub seed: Option<u64>,
+    // Where the replays of the matches are saved
+    pub replay_dir: Option<PathBuf>,
+    // Matches to play before leaving, forever without it
+    pub matches: Option<u32>,
+    pub played: u32,
+    // Last match played, for the results
+    pub last_match: Option<Replay>,
+    // Match loaded to play back or view
+    pub replay: Option<Replay>,
+    // Measured by the timestep
+    pub rates: Rates,
+}
+impl Session {
+    pub fn new(
+        key_bindings: KeyBindings,
+        seed: Option<u64>,
+        replay_dir: Option<PathBuf>,
+        matches: Option<u32>,
+    ) -> Self {
+        Self {
+            key_bindings,
+            seed,
+            replay_dir,
+            matches,
+            played: 0,
+            last_match: None,
+            replay: None,
+            rates: Rates::default(),
+        }
+    }
+
+    // Whether all the matches to play were played
+    pub fn is_done(&self) -> bool {
+        self.matches.is_some_and(|matches| self.played >= matches)
+    }
+}
+
+// Inputs read on a tick
+pub struct Input {
+    // Keys held
+    pub keycodes: Keycodes,
+    // Keys pressed on this tick
+    pub pressed: Keycodes,
+    pub button_events: ButtonEvents,
+}
+impl Input {
+    fn read<D: Display>(interfaces: &mut Interfaces<D>, last_keycodes: &Keycodes) -> Self {
+        let keycodes = interfaces.keyboard.get_keycodes();
+        let button_events = interfaces.buttons.poll();
+        let pressed = keycodes
+            .iter()
+            .filter(|keycode| !last_keycodes.contains(keycode))
+            .copied()
+            .collect();
+        Self {
+            keycodes,
+            pressed,
+            button_events,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Press {
+    #[default]
+    None,
+    Short,
+    Long,
+}
+
+// Presses of the buttons which act when released, unless they were long. A
+// press which started before the scene is ignored.
+#[derive(Default)]
+pub struct Presses {
+    pressing: [bool; 2],
+}
+impl Presses {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Presses of button 1 and 2 which ended in `events`
+    pub fn read(&mut self, events: &ButtonEvents) -> [Press; 2] {
+        let mut presses = [Press::None; 2];
+        for event in events {
+            let i = match event.button {
+                Button::Button1 => 0,
+                Button::Button2 => 1,
+            };
+            match event.kind {
+                ButtonEventKind::Pressed => self.pressing[i] = true,
+                ButtonEventKind::LongPress if self.pressing[i] => {
+                    self.pressing[i] = false;
+                    presses[i] = Press::Long;
+                }
+                ButtonEventKind::Released if self.pressing[i] => {
+                    self.pressing[i] = false;
+                    presses[i] = Press::Short;
+                }
+                _ => (),
+            }
+        }
+        presses
+    }
+}
+
+// Text style and line height to fit `lines` lines in `height` pixels, a smaller
+// font on the 128x32 panels
+pub fn fit_lines(height: u32, lines: u32) -> (MonoTextStyle<'static, BinaryColor>, i32) {
+    let font = if lines * FONT_6X10.character_size.height <= height {
+        &FONT_6X10
+    } else {
+        &FONT_4X6
+    };
+    (
+        MonoTextStyle::new(font, BinaryColor::On),
+        font.character_size.height as i32,
+    )
+}
+
+pub trait Scene<D: Display> {
+    fn enter(
+        &mut self,
+        _interfaces: &mut Interfaces<D>,
+        _session: &mut Session,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    // Advance a tick, the LEDs and the sound are ticked afterwards
+    fn update(
+        &mut self,
+        interfaces: &mut Interfaces<D>,
+        session: &mut Session,
+        input: &Input,
+    ) -> anyhow::Result<Transition>;
+
+    // Draw on a display which is flushed afterwards
+    fn render(&self, session: &Session, display: &mut D) -> Result<(), DisplayError>;
+
+    fn exit(
+        &mut self,
+        _interfaces: &mut Interfaces<D>,
+        _session: &mut Session,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+fn new_scene<D: Display>(
+    id: SceneId,
+    session: &Session,
+    size: Size,
+) -> anyhow::Result<Box<dyn Scene<GifRecorder<D>>>> {
+    let replay = || session.replay.as_ref().context("no replay is loaded");
+    Ok(match id {
+        SceneId::Boot => Box::new(BootScene::new()),
+        SceneId::Title => Box::new(TitleScene::new()),
+        SceneId::Menu => Box::new(MenuScene::new()),
+        SceneId::Match => Box::new(MatchScene::new(session, Arena::new(size))),
+        SceneId::Results => Box::new(ResultsScene::new()),
+        SceneId::KeyBinding => Box::new(KeyBindingScene::new()),
+        SceneId::Diagnostics => Box::new(DiagnosticsScene::new()),
+        SceneId::Replay => Box::new(ReplayScene::new(replay()?)),
+        SceneId::Viewer => Box::new(ViewerScene::new(replay()?)),
+    })
+}
+
+// The scene running and the keys held on the last tick
+struct Scenes<D: Display> {
+    id: SceneId,
+    scene: Box<dyn Scene<GifRecorder<D>>>,
+    last_keycodes: Keycodes,
+}
+impl<D: Display> Scenes<D> {
+    fn start(
+        first: SceneId,
+        interfaces: &mut Interfaces<GifRecorder<D>>,
+        session: &mut Session,
+    ) -> anyhow::Result<Self> {
+        let mut scene = new_scene(first, session, interfaces.display.size())?;
+        scene.enter(interfaces, session)?;
+        Ok(Self {
+            id: first,
+            scene,
+            // Keys are only read on ticks, a read before the first would take
+            // a tick of a replayed capture
+            last_keycodes: Keycodes::new(),
+        })
+    }
+
+    // Read the inputs, update the scene and switch scenes, false once the
+    // game is left
+    fn tick(
+        &mut self,
+        interfaces: &mut Interfaces<GifRecorder<D>>,
+        session: &mut Session,
+    ) -> anyhow::Result<bool> {
+        // Input
+        let input = Input::read(interfaces, &self.last_keycodes);
+        if interfaces.keyboard.quit_requested() {
+            // left as after a quit, so the match is still saved
+            self.scene.exit(interfaces, session)?;
+            return Ok(false);
+        }
+
+        // Update
+        let transition = self.scene.update(interfaces, session, &input)?;
+        self.last_keycodes = input.keycodes;
+        interfaces.leds.tick();
+        interfaces.sound.tick();
+        interfaces.display.tick();
+        match transition {
+            Transition::Stay => (),
+            Transition::To(id) => {
+                self.scene.exit(interfaces, session)?;
+                self.scene = new_scene(id, session, interfaces.display.size())?;
+                self.id = id;
+                self.scene.enter(interfaces, session)?;
+            }
+            Transition::Quit => {
+                self.scene.exit(interfaces, session)?;
+                // let the last sound end
+                interfaces.sound.finish();
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+// Run the scenes from `first` until one quits
+pub fn run_scenes<D: Display>(
+    interfaces: &mut Interfaces<GifRecorder<D>>,
+    session: &mut Session,
+    first: SceneId,
+) -> anyhow::Result<()> {
+    let mut timestep = Timestep::new(config().tick.tick_size());
+    let mut scenes = Scenes::start(first, interfaces, session)?;
+    while scenes.tick(interfaces, session)? {
+        // Draw on display, skipped while the ticks are behind
+        if timestep.render_due() {
+            session.rates = timestep.rates();
+            scenes
+                .scene
+                .render(session, &mut interfaces.display)
+                .map_err(|e| anyhow!("failed to draw: {e:?}"))?;
+            interfaces
+                .display
+                .flush()
+                .map_err(|e| anyhow!("failed to flush the display: {e:?}"))?;
+        }
+
+        // Wait for the next tick
+        timestep.wait();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constant::{BOOT_HOLD_TIME, BOOT_RING_TIME, RESULTS_TIMEOUT, TITLE_TIMEOUT};
+    use crate::interface::{
+        debounce::DebouncedButtons,
+        headless::HeadlessDisplay,
+        mock::{ScriptedButtons, TickCounter},
+        null::{NoBuzzer, NoKeys, NoLed},
+        ButtonLevels, Keyboard, Leds,
+        Level::{High, Low},
+    };
+    use std::time::Duration;
+
+    fn ticks(duration: Duration) -> u64 {
+        config().tick.ticks(duration)
+    }
+
+    // Ticks until `ms` milliseconds passed
+    fn ms(ms: u64) -> u64 {
+        ms.div_ceil(config().tick.tick_ms)
+    }
+
+    // Button levels from a tick on
+    fn at(tick: u64, button1: bool, button2: bool) -> (u64, ButtonLevels) {
+        let level = |pressed| if pressed { Low } else { High };
+        (tick, ButtonLevels::new(level(button1), level(button2)))
+    }
+
+    // Scenes on the headless display as `run_scenes` runs them, without
+    // waiting for the ticks or drawing
+    struct Run {
+        interfaces: Interfaces<GifRecorder<HeadlessDisplay>>,
+        session: Session,
+        scenes: Scenes<HeadlessDisplay>,
+        ticks: TickCounter,
+    }
+    impl Run {
+        fn start(first: SceneId, script: Vec<(u64, ButtonLevels)>, mut session: Session) -> Self {
+            let ticks = TickCounter::new();
+            let mut interfaces = Interfaces::new(
+                GifRecorder::new(
+                    HeadlessDisplay::new(config().display.size.size(), None),
+                    None,
+                ),
+                Box::new(ScriptedButtons::new(ticks.clone(), script)),
+                Leds::new(Box::new(NoLed), Box::new(NoLed)),
+                Box::new(NoKeys),
+                Box::new(NoBuzzer),
+            );
+            let scenes = Scenes::start(first, &mut interfaces, &mut session).unwrap();
+            Self {
+                interfaces,
+                session,
+                scenes,
+                ticks,
+            }
+        }
+
+        // Tick until the scene changes or the game is left, with the tick it
+        // happened on
+        fn until_change(&mut self) -> (Option<SceneId>, u64) {
+            let id = self.scenes.id;
+            while self.ticks.get() < 100_000 {
+                if !self
+                    .scenes
+                    .tick(&mut self.interfaces, &mut self.session)
+                    .unwrap()
+                {
+                    return (None, self.ticks.get());
+                }
+                if self.scenes.id != id {
+                    return (Some(self.scenes.id), self.ticks.get());
+                }
+            }
+            panic!("still in {id:?}");
+        }
+    }
+
+    fn session() -> Session {
+        Session::new(config().key_bindings(), Some(1), None, None)
+    }
+
+    // Presses read on every tick of `script`
+    fn presses(script: Vec<(u64, ButtonLevels)>, from: u64, to: u64) -> Vec<(u64, [Press; 2])> {
+        let ticks = TickCounter::new();
+        let mut buttons =
+            DebouncedButtons::new(Box::new(ScriptedButtons::new(ticks.clone(), script)));
+        let mut presses = Presses::new();
+        let mut read = Vec::new();
+        while ticks.get() < to {
+            let events = buttons.poll();
+            if ticks.get() >= from {
+                let pressed = presses.read(&events);
+                if pressed != [Press::None; 2] {
+                    read.push((ticks.get(), pressed));
+                }
+            }
+        }
+        read
+    }
+
+    #[test]
+    fn short_and_long_presses() {
+        let debounce = ms(20);
+        let script = vec![
+            at(10, true, false),
+            at(20, false, false),
+            at(100, false, true),
+            at(100 + ms(800) + 50, false, false),
+        ];
+        assert_eq!(
+            presses(script, 0, 2000),
+            [
+                // when released
+                (20 + debounce, [Press::Short, Press::None]),
+                // when held long enough, not when released
+                (100 + ms(800), [Press::None, Press::Long]),
+            ]
+        );
+    }
+
+    #[test]
+    fn press_before_the_scene_ignored() {
+        let script = vec![
+            at(10, true, true),
+            at(50, false, true),
+            at(60 + ms(800), false, false),
+        ];
+        // Pressed before tick 20, button 1 released and button 2 long pressed
+        // after
+        assert_eq!(presses(script.clone(), 20, 2000), []);
+        assert_eq!(
+            presses(script, 0, 2000),
+            [
+                (50 + ms(20), [Press::Short, Press::None]),
+                (10 + ms(800), [Press::None, Press::Long]),
+            ]
+        );
+    }
+
+    #[test]
+    fn boot_to_title() {
+        let mut run = Run::start(SceneId::Boot, Vec::new(), session());
+        assert_eq!(
+            run.until_change(),
+            (
+                Some(SceneId::Title),
+                ticks(BOOT_RING_TIME) + ticks(BOOT_HOLD_TIME)
+            )
+        );
+    }
+
+    #[test]
+    fn boot_hold_opens_key_bindings() {
+        let mut run = Run::start(SceneId::Boot, vec![at(100, true, true)], session());
+        assert_eq!(
+            run.until_change(),
+            (
+                Some(SceneId::KeyBinding),
+                ticks(BOOT_RING_TIME) + ticks(BOOT_HOLD_TIME)
+            )
+        );
+        // Only one button is not enough
+        let mut run = Run::start(SceneId::Boot, vec![at(100, true, false)], session());
+        assert_eq!(run.until_change().0, Some(SceneId::Title));
+    }
+
+    #[test]
+    fn title_idle_timeout() {
+        let mut run = Run::start(SceneId::Title, Vec::new(), session());
+        assert_eq!(
+            run.until_change(),
+            (Some(SceneId::Match), ticks(TITLE_TIMEOUT))
+        );
+        // A press counts as activity, the timeout starts over after it
+        let script = vec![at(100, false, true), at(100 + ms(800), false, false)];
+        let mut run = Run::start(SceneId::Title, script, session());
+        assert_eq!(
+            run.until_change(),
+            (
+                Some(SceneId::Match),
+                100 + ms(800) + ms(20) + ticks(TITLE_TIMEOUT)
+            )
+        );
+    }
+
+    #[test]
+    fn results_timeout() {
+        let mut run = Run::start(SceneId::Results, Vec::new(), session());
+        assert_eq!(
+            run.until_change(),
+            (Some(SceneId::Title), ticks(RESULTS_TIMEOUT))
+        );
+        // Leaves once the matches are played
+        let mut session = Session::new(config().key_bindings(), Some(1), None, Some(2));
+        session.played = 1;
+        let mut run = Run::start(SceneId::Results, Vec::new(), session);
+        assert_eq!(run.until_change().0, Some(SceneId::Title));
+        run.session.played = 2;
+        let mut run = Run::start(SceneId::Results, Vec::new(), run.session);
+        assert_eq!(run.until_change(), (None, ticks(RESULTS_TIMEOUT)));
+    }
+
+    #[test]
+    fn menu_navigation() {
+        // Button 1 twice to the diagnostics, button 2 opens them
+        let script = vec![
+            at(10, true, false),
+            at(30, false, false),
+            at(50, true, false),
+            at(70, false, false),
+            at(90, false, true),
+            at(110, false, false),
+        ];
+        let mut run = Run::start(SceneId::Menu, script, session());
+        assert_eq!(
+            run.until_change(),
+            (Some(SceneId::Diagnostics), 110 + ms(20))
+        );
+        // The cursor wraps around to the title, and a long press goes back to
+        // the title as well
+        let mut script = Vec::new();
+        for i in 0..4 {
+            script.push(at(10 + i * 40, true, false));
+            script.push(at(30 + i * 40, false, false));
+        }
+        script.push(at(200, false, true));
+        script.push(at(220, false, false));
+        let mut run = Run::start(SceneId::Menu, script, session());
+        assert_eq!(run.until_change(), (Some(SceneId::Match), 220 + ms(20)));
+        let mut run = Run::start(SceneId::Menu, vec![at(10, false, true)], session());
+        assert_eq!(run.until_change(), (Some(SceneId::Title), 10 + ms(800)));
+    }
+
+    // Ctrl-C of the terminal keyboard from a tick on
+    struct Interrupt(TickCounter, u64);
+    impl Keyboard for Interrupt {
+        fn get_keycodes(&mut self) -> Keycodes {
+            Keycodes::new()
+        }
+        fn quit_requested(&self) -> bool {
+            self.0.get() >= self.1
+        }
+    }
+
+    #[test]
+    fn quit_request_leaves_the_scene() {
+        let mut run = Run::start(SceneId::Match, Vec::new(), session());
+        run.interfaces.keyboard = Box::new(Interrupt(run.ticks.clone(), 100));
+        assert_eq!(run.until_change(), (None, 100));
+        // The match was left as it is after it ends
+        let last_match = run.session.last_match.unwrap();
+        assert_eq!(last_match.outcome, None);
+    }
+}
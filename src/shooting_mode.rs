@@ -1,248 +1,407 @@
+use crate::config::config;
 use crate::constant::{
-    BULLET_DAMEGE, CLEAN_INTERVAL, DISPLAY_MARGIN, DISPLAY_SIZE_X, DISPLAY_SIZE_Y, HIT_DISTANCE,
-    INITIAL_HITPOINT, RESULT_TICK_SIZE, SHOOT_INTERVAL, TICK_SIZE,
+    LOW_HITPOINT_BREATHE_PERIOD, START_COUNTDOWN_BEATS, START_COUNTDOWN_TICKS, WIN_BLINK_PERIOD,
 };
-use crate::interface::Interfaces;
-use crate::object::{Bullets, Guns, Lasers, Players, Status, Team};
+use crate::game::{render, GameEvent, GameState, Inputs};
+use crate::interface::{
+    debounce::{has_event, Button, ButtonEventKind},
+    gif_recorder::GifRecorder,
+    led_pattern::Pattern,
+    Display, Interfaces,
+};
+use crate::object::{Arena, Team};
+use crate::replay::Replay;
+use crate::scene::{Input, Scene, SceneId, Session, Transition};
+use crate::sound::Effect;
+use crate::timestep::Rates;
+use anyhow::ensure;
+use display_interface::DisplayError;
 use embedded_graphics::{
-    mono_font::{ascii::FONT_10X20, MonoTextStyle},
+    mono_font::{ascii::FONT_4X6, MonoTextStyle},
     pixelcolor::BinaryColor,
     prelude::*,
-    primitives::{Line, PrimitiveStyleBuilder},
-    text::{Alignment, Baseline, Text, TextStyleBuilder},
+    text::{Baseline, Text},
 };
-use std::thread;
-
-// Shouting Mode
-pub fn shooting(interfaces: &mut Interfaces) {
-    let mut mono_hitpoint = INITIAL_HITPOINT;
-    let mut di_hitpoint = INITIAL_HITPOINT;
-    let mut tick: u128 = 0;
-    let mut winner: Option<Team> = None;
-    let mut mono_result_text: Option<&str> = None;
-    let mut di_result_text: Option<&str> = None;
-    let mut tick_for_exit: Option<i32> = None;
-    let mut shooting_interval: u8 = 0;
-    let mut clean_interval: u8 = CLEAN_INTERVAL;
-
-    let character_style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
-    let text_style = TextStyleBuilder::new().baseline(Baseline::Top);
-    let mono_text_style = text_style.alignment(Alignment::Left).build();
-    let di_text_style = text_style.alignment(Alignment::Right).build();
-    let hitpoint_bar_stroke = PrimitiveStyleBuilder::new()
-        .stroke_color(BinaryColor::On)
-        .stroke_width(1)
-        .build();
-
-    let mut players = Players::new();
-    let mut guns = Guns::new();
-    let mut lasers = Lasers::new();
-    let mut bullets_mono: Bullets = Vec::new();
-    let mut bullets_di: Bullets = Vec::new();
-
-    // Main Loop
-    loop {
-        // Tick
-        tick += 1;
-        let status = Status::new(interfaces);
-
-        // Process par tick
-        // player
-        players.player1.tick(&status);
-        players.player2.tick(&status);
-        // bullets
-        for bullet in &mut bullets_mono {
-            if bullet.active {
-                bullet.tick();
-            }
-        }
-        for bullet in &mut bullets_di {
-            if bullet.active {
-                bullet.tick();
-            }
-        }
 
-        // Get players position
-        let p1_pos = players.player1.get_position();
-        let p2_pos = players.player2.get_position();
-
-        // Shoot
-        if shooting_interval == 0 {
-            shooting_interval = (SHOOT_INTERVAL / (tick / 2 + 100)) as u8;
-            bullets_mono.push(guns.gun1.shoot(p1_pos.x));
-            bullets_di.push(guns.gun2.shoot(p2_pos.x));
-        } else {
-            shooting_interval -= 1;
+// Match scene, plays a match which is kept as a replay for the results
+pub struct MatchScene {
+    state: GameState,
+    replay: Replay,
+}
+impl MatchScene {
+    pub fn new(session: &Session, arena: Arena) -> Self {
+        let seed = session.seed.unwrap_or_else(rand::random);
+        Self {
+            state: GameState::new(&session.key_bindings, arena, seed),
+            replay: Replay::new(seed, arena, &session.key_bindings),
         }
+    }
+}
 
-        if winner.is_none() {
-            // Laser
-            // laser1
-            if let Some(bullet) = lasers.laser1.try_emit(
-                &mut interfaces.leds.led1,
-                p2_pos.y,
-                status.button_levels.button1_level,
-            ) {
-                bullets_mono.push(bullet);
-            }
-            // laser2
-            if let Some(bullet) = lasers.laser2.try_emit(
-                &mut interfaces.leds.led2,
-                p1_pos.y,
-                status.button_levels.button2_level,
-            ) {
-                bullets_di.push(bullet);
-            }
+impl<D: Display> Scene<GifRecorder<D>> for MatchScene {
+    fn enter(
+        &mut self,
+        interfaces: &mut Interfaces<GifRecorder<D>>,
+        _session: &mut Session,
+    ) -> anyhow::Result<()> {
+        interfaces.display.start()?;
+        interfaces.sound.play(Effect::Start);
+        Ok(())
+    }
 
-            // Hit
-            // player and bullet
-            for b in bullets_di.iter_mut() {
-                if b.active {
-                    let b_pos = b.get_position();
-                    if (p1_pos.x - b_pos.x).abs() + (p1_pos.y - b_pos.y).abs() <= HIT_DISTANCE {
-                        mono_hitpoint -= BULLET_DAMEGE;
-                        b.disable();
-                    }
-                }
-            }
-            for b in bullets_mono.iter_mut() {
-                if b.active {
-                    let b_pos = b.get_position();
-                    if (p2_pos.x - b_pos.x).abs() + (p2_pos.y - b_pos.y).abs() <= HIT_DISTANCE {
-                        di_hitpoint -= BULLET_DAMEGE;
-                        b.disable();
-                    }
-                }
-            }
-            // bullet and bullet
-            for m in bullets_mono.iter_mut() {
-                for d in bullets_di.iter_mut() {
-                    if m.active && d.active {
-                        let m_pos = m.get_position();
-                        let d_pos = d.get_position();
-                        if (m_pos.x - d_pos.x).abs() + (m_pos.y - d_pos.y).abs() <= HIT_DISTANCE {
-                            m.disable();
-                            d.disable();
-                        }
-                    }
-                }
-            }
+    fn update(
+        &mut self,
+        interfaces: &mut Interfaces<GifRecorder<D>>,
+        session: &mut Session,
+        input: &Input,
+    ) -> anyhow::Result<Transition> {
+        // Keys held and laser buttons pressed on this tick
+        let inputs = Inputs {
+            keycodes: input.keycodes.clone(),
+            laser1: has_event(
+                &input.button_events,
+                Button::Button1,
+                ButtonEventKind::Pressed,
+            ),
+            laser2: has_event(
+                &input.button_events,
+                Button::Button2,
+                ButtonEventKind::Pressed,
+            ),
+        };
+        self.replay.record(self.state.tick + 1, &inputs);
+        step(interfaces, &mut self.state, &inputs);
+        if !self.state.is_over() {
+            return Ok(Transition::Stay);
         }
+        self.replay.finish(&self.state);
+        session.played += 1;
+        Ok(Transition::To(SceneId::Results))
+    }
 
-        // Remove bullets in outside of display
-        if clean_interval == 0 {
-            clean_interval = CLEAN_INTERVAL;
-            bullets_mono.retain(|b| {
-                b.active && {
-                    let pos = b.get_position();
-                    (-DISPLAY_MARGIN < pos.x && pos.x < DISPLAY_SIZE_X + DISPLAY_MARGIN)
-                        && (-DISPLAY_MARGIN < pos.y && pos.y < DISPLAY_SIZE_Y + DISPLAY_MARGIN)
-                }
-            });
-            bullets_di.retain(|b| {
-                b.active && {
-                    let pos = b.get_position();
-                    (-DISPLAY_MARGIN < pos.x && pos.x < DISPLAY_SIZE_X + DISPLAY_MARGIN)
-                        && (-DISPLAY_MARGIN < pos.y && pos.y < DISPLAY_SIZE_Y + DISPLAY_MARGIN)
-                }
-            });
-        } else {
-            clean_interval -= 1;
+    fn render(&self, session: &Session, display: &mut GifRecorder<D>) -> Result<(), DisplayError> {
+        draw(&self.state, session.rates, display)
+    }
+
+    fn exit(
+        &mut self,
+        interfaces: &mut Interfaces<GifRecorder<D>>,
+        session: &mut Session,
+    ) -> anyhow::Result<()> {
+        interfaces.leds.led1.set(Pattern::Off);
+        interfaces.leds.led2.set(Pattern::Off);
+        interfaces.leds.tick();
+        interfaces.display.finish()?;
+        if let Some(directory) = &session.replay_dir {
+            let name = chrono::Local::now().format("match-%Y%m%d-%H%M%S.replay");
+            self.replay.save(&directory.join(name.to_string()))?;
         }
+        session.last_match = Some(self.replay.clone());
+        Ok(())
+    }
+}
 
-        // Draw on display
-        // clear display
-        interfaces.display.clear(BinaryColor::Off).unwrap();
+// Replay scene, plays a recorded match back and checks that it ends as it did
+pub struct ReplayScene {
+    state: GameState,
+    replay: Replay,
+}
+impl ReplayScene {
+    pub fn new(replay: &Replay) -> Self {
+        Self {
+            state: GameState::new(&replay.key_bindings, replay.arena, replay.seed),
+            replay: replay.clone(),
+        }
+    }
+}
 
-        // draw players
-        players.player1.draw(&mut interfaces.display);
-        players.player2.draw(&mut interfaces.display);
+impl<D: Display> Scene<GifRecorder<D>> for ReplayScene {
+    fn enter(
+        &mut self,
+        interfaces: &mut Interfaces<GifRecorder<D>>,
+        _session: &mut Session,
+    ) -> anyhow::Result<()> {
+        check_arena(&self.replay, interfaces.display.size())?;
+        self.replay.check_config()?;
+        interfaces.display.start()?;
+        interfaces.sound.play(Effect::Start);
+        Ok(())
+    }
 
-        // draw bullets
-        for bullet in &mut bullets_mono {
-            if bullet.active {
-                bullet.draw(&mut interfaces.display);
-            }
+    fn update(
+        &mut self,
+        interfaces: &mut Interfaces<GifRecorder<D>>,
+        _session: &mut Session,
+        _input: &Input,
+    ) -> anyhow::Result<Transition> {
+        let inputs = self.replay.inputs(self.state.tick + 1);
+        step(interfaces, &mut self.state, &inputs);
+        if !self.state.is_over() {
+            return Ok(Transition::Stay);
         }
-        for bullet in &mut bullets_di {
-            if bullet.active {
-                bullet.draw(&mut interfaces.display);
-            }
+        self.replay.check(&self.state)?;
+        Ok(Transition::Quit)
+    }
+
+    fn render(&self, session: &Session, display: &mut GifRecorder<D>) -> Result<(), DisplayError> {
+        draw(&self.state, session.rates, display)
+    }
+
+    fn exit(
+        &mut self,
+        interfaces: &mut Interfaces<GifRecorder<D>>,
+        _session: &mut Session,
+    ) -> anyhow::Result<()> {
+        interfaces.leds.led1.set(Pattern::Off);
+        interfaces.leds.led2.set(Pattern::Off);
+        interfaces.leds.tick();
+        interfaces.display.finish()
+    }
+}
+
+// A replay plays only on a display of the size it was recorded on
+pub fn check_arena(replay: &Replay, size: Size) -> anyhow::Result<()> {
+    ensure!(
+        Arena::new(size) == replay.arena,
+        "the replay was recorded on a {}x{} display",
+        replay.arena.width,
+        replay.arena.height
+    );
+    Ok(())
+}
+
+// Step `state` a tick with `inputs`, sounding its events and setting the LEDs
+fn step<D: Display>(interfaces: &mut Interfaces<D>, state: &mut GameState, inputs: &Inputs) {
+    for event in state.step(inputs) {
+        if let Some(effect) = sound_effect(event) {
+            interfaces.sound.play(effect);
         }
+    }
+    show_leds(interfaces, state);
+}
 
-        // Draw hitpoint bar
-        Line::new(Point::new(0, 0), Point::new(mono_hitpoint.into(), 0))
-            .into_styled(hitpoint_bar_stroke)
-            .draw(&mut interfaces.display)
-            .unwrap();
-        Line::new(
-            Point::new(DISPLAY_SIZE_X - (di_hitpoint as i32), 0),
-            Point::new(DISPLAY_SIZE_X, 0),
+// Draw `state`, and the rates and the most bullets a team had in flight if
+// `tick.show_rates`
+fn draw<D>(state: &GameState, rates: Rates, display: &mut D) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    render(state, display)?;
+    if config().tick.show_rates {
+        let peak = state.bullets_mono.peak().max(state.bullets_di.peak());
+        Text::with_baseline(
+            &format!(
+                "{:.0}t/s {:.0}f/s peak {peak}",
+                rates.ticks_per_sec, rates.frames_per_sec
+            ),
+            Point::new(0, state.arena.height),
+            MonoTextStyle::new(&FONT_4X6, BinaryColor::On),
+            Baseline::Bottom,
         )
-        .into_styled(hitpoint_bar_stroke)
-        .draw(&mut interfaces.display)
-        .unwrap();
-
-        // Decide winner
-        match (winner, mono_hitpoint, di_hitpoint) {
-            (None, mono_hp, di_hp) if (mono_hp <= 0 || di_hp <= 0) && mono_hp > di_hp => {
-                (mono_result_text, di_result_text) = (Some("Win"), Some("Lose"));
-                winner = Some(Team::Mono);
-            }
-            (None, mono_hp, di_hp) if (mono_hp <= 0 || di_hp <= 0) && di_hp > mono_hp => {
-                (mono_result_text, di_result_text) = (Some("Lose"), Some("Win"));
-                winner = Some(Team::Di);
-            }
-            (None, mono_hp, di_hp) if (mono_hp <= 0 || di_hp <= 0) && mono_hp == di_hp => {
-                mono_hitpoint = BULLET_DAMEGE;
-                di_hitpoint = BULLET_DAMEGE;
+        .draw(display)?;
+    }
+    Ok(())
+}
+
+// Sound of a game event
+pub fn sound_effect(event: GameEvent) -> Option<Effect> {
+    match event {
+        GameEvent::Shot => Some(Effect::Shot),
+        GameEvent::Hit(_) => Some(Effect::Hit),
+        GameEvent::LaserFired(_) => Some(Effect::Laser),
+        GameEvent::Won(Team::Mono) => Some(Effect::Win),
+        GameEvent::Won(Team::Di) => Some(Effect::Lose),
+        GameEvent::LaserReady(_) | GameEvent::SuddenDeath => None,
+    }
+}
+
+// Set the LEDs of the players for `state`
+pub fn show_leds<D: Display>(interfaces: &mut Interfaces<D>, state: &GameState) {
+    let countdown = state.tick <= START_COUNTDOWN_TICKS as u128;
+    interfaces.leds.led1.set(led_pattern(
+        state.winner.map(|team| team == Team::Mono),
+        state.lasers.laser1.is_ready(),
+        countdown,
+        state.mono_hitpoint,
+    ));
+    interfaces.leds.led2.set(led_pattern(
+        state.winner.map(|team| team == Team::Di),
+        state.lasers.laser2.is_ready(),
+        countdown,
+        state.di_hitpoint,
+    ));
+}
+
+// What the LED of a player shows, the first that applies of: the result, a
+// ready laser, the start countdown and low hitpoints
+fn led_pattern(won: Option<bool>, laser_ready: bool, countdown: bool, hitpoint: i8) -> Pattern {
+    match won {
+        Some(true) => Pattern::DoubleBlink {
+            period: WIN_BLINK_PERIOD,
+        },
+        Some(false) => Pattern::Off,
+        None if laser_ready => Pattern::On,
+        None if countdown => Pattern::Countdown {
+            ticks: START_COUNTDOWN_TICKS,
+            beats: START_COUNTDOWN_BEATS,
+        },
+        None if hitpoint <= config().hitpoint.initial / 4 => Pattern::Breathe {
+            period: LOW_HITPOINT_BREATHE_PERIOD,
+        },
+        None => Pattern::Off,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interface::{
+        debounce::ButtonEvents,
+        headless::HeadlessDisplay,
+        mock::{LedLog, LedWrite, RecordingLed, ScriptedButtons, TickCounter},
+        null::{NoBuzzer, NoKeys},
+        ButtonLevels, Leds, Level,
+    };
+
+    const SEED: u64 = 1;
+
+    // A match on the software GPIO, as `main` sets it up without a display
+    struct Run {
+        interfaces: Interfaces<GifRecorder<HeadlessDisplay>>,
+        session: Session,
+        scene: MatchScene,
+        ticks: TickCounter,
+        led1: LedLog,
+        led2: LedLog,
+    }
+    impl Run {
+        fn start(script: Vec<(u64, ButtonLevels)>) -> Self {
+            let ticks = TickCounter::new();
+            let (led1, led2) = (
+                RecordingLed::new(ticks.clone()),
+                RecordingLed::new(ticks.clone()),
+            );
+            let (log1, log2) = (led1.log(), led2.log());
+            let display = HeadlessDisplay::new(config().display.size.size(), None);
+            let mut interfaces = Interfaces::new(
+                GifRecorder::new(display, None),
+                Box::new(ScriptedButtons::new(ticks.clone(), script)),
+                Leds::new(Box::new(led1), Box::new(led2)),
+                Box::new(NoKeys),
+                Box::new(NoBuzzer),
+            );
+            let mut session = Session::new(config().key_bindings(), Some(SEED), None, Some(1));
+            let mut scene = MatchScene::new(&session, Arena::new(interfaces.display.size()));
+            scene.enter(&mut interfaces, &mut session).unwrap();
+            Self {
+                interfaces,
+                session,
+                scene,
+                ticks,
+                led1: log1,
+                led2: log2,
             }
-            _ => (),
         }
 
-        // Draw result
-        if winner.is_some() {
-            Text::with_text_style(
-                mono_result_text.unwrap(),
-                Point::zero(),
-                character_style,
-                mono_text_style,
-            )
-            .draw(&mut interfaces.display)
-            .unwrap();
-            Text::with_text_style(
-                di_result_text.unwrap(),
-                Point::new(DISPLAY_SIZE_X, 0),
-                character_style,
-                di_text_style,
-            )
-            .draw(&mut interfaces.display)
-            .unwrap();
+        // A tick as `run_scenes` runs it
+        fn tick(&mut self) -> (Transition, ButtonEvents) {
+            let button_events = self.interfaces.buttons.poll();
+            let input = Input {
+                keycodes: Vec::new(),
+                pressed: Vec::new(),
+                button_events: button_events.clone(),
+            };
+            let transition = self
+                .scene
+                .update(&mut self.interfaces, &mut self.session, &input)
+                .unwrap();
+            self.interfaces.leds.tick();
+            self.interfaces.sound.tick();
+            (transition, button_events)
         }
 
-        // Flush display
-        interfaces.display.flush().unwrap();
+        // Tick until the laser of player 1 is ready
+        fn until_laser1_ready(&mut self) -> u64 {
+            while !self.scene.state.lasers.laser1.is_ready() {
+                self.tick();
+                assert!(!self.scene.state.is_over(), "the laser was never ready");
+            }
+            self.ticks.get()
+        }
+    }
+
+    fn last(log: &LedLog) -> LedWrite {
+        *log.borrow().last().unwrap()
+    }
 
-        // Exit
-        match tick_for_exit {
-            Some(0) => {
-                break;
+    #[test]
+    fn led_lights_when_laser_ready() {
+        let mut run = Run::start(Vec::new());
+        let ready = run.until_laser1_ready();
+        assert!(ready > START_COUNTDOWN_TICKS, "ready during the countdown");
+        assert_eq!(
+            last(&run.led1),
+            LedWrite {
+                tick: ready,
+                level: Level::High
             }
-            Some(t) => {
-                tick_for_exit = Some(t - 1);
+        );
+    }
+
+    #[test]
+    fn led_off_when_laser_fired() {
+        let ready = Run::start(Vec::new()).until_laser1_ready();
+        // Same match, button 1 pressed the tick after the laser is ready
+        let pressed = ButtonLevels::new(Level::Low, Level::High);
+        let released = ButtonLevels::new(Level::High, Level::High);
+        let mut run = Run::start(vec![(ready + 1, pressed), (ready + 30, released)]);
+        assert_eq!(run.until_laser1_ready(), ready);
+        let fired = loop {
+            let (_, events) = run.tick();
+            if has_event(&events, Button::Button1, ButtonEventKind::Pressed) {
+                break run.ticks.get();
             }
-            None => {
-                if winner.is_some() {
-                    tick_for_exit = Some(RESULT_TICK_SIZE);
+        };
+        assert!(!run.scene.state.lasers.laser1.is_ready());
+        let writes: Vec<LedWrite> = run
+            .led1
+            .borrow()
+            .iter()
+            .filter(|write| write.tick >= ready)
+            .copied()
+            .collect();
+        assert_eq!(
+            writes,
+            [
+                LedWrite {
+                    tick: ready,
+                    level: Level::High
+                },
+                LedWrite {
+                    tick: fired,
+                    level: Level::Low
                 }
-            }
-        }
-
-        // Sleep
-        thread::sleep(TICK_SIZE);
+            ]
+        );
     }
 
-    // Finalize
-    interfaces.leds.led1.set_low();
-    interfaces.leds.led2.set_low();
+    #[test]
+    fn leds_low_after_match() {
+        let mut run = Run::start(Vec::new());
+        while run.tick().0 == Transition::Stay {}
+        run.scene
+            .exit(&mut run.interfaces, &mut run.session)
+            .unwrap();
+        let end = run.ticks.get();
+        assert!(end > START_COUNTDOWN_TICKS);
+        for log in [&run.led1, &run.led2] {
+            assert_eq!(last(log).level, Level::Low);
+        }
+        // The winner was still blinking when the match ended
+        let winner = match run.scene.state.winner {
+            Some(Team::Mono) => &run.led1,
+            _ => &run.led2,
+        };
+        assert_eq!(last(winner).tick, end);
+    }
 }
@@ -0,0 +1,397 @@
+// Sound effects and jingles for the buzzer. Melodies are written in RTTTL,
+// `name:d=<duration>,o=<octave>,b=<bpm>:<notes>`, and played one note at a
+// time off the game tick.
+use crate::config::config;
+use crate::interface::Buzzer;
+use anyhow::{bail, ensure, Context};
+use std::{collections::VecDeque, iter::Peekable, str::Chars, thread, time::Duration};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Note {
+    // `None` is a rest
+    pub frequency: Option<u32>,
+    pub duration: Duration,
+}
+
+pub type Melody = Vec<Note>;
+
+// Semitones above C of the note letters
+fn semitone(letter: char) -> Option<i32> {
+    match letter {
+        'c' => Some(0),
+        'd' => Some(2),
+        'e' => Some(4),
+        'f' => Some(5),
+        'g' => Some(7),
+        'a' => Some(9),
+        'b' | 'h' => Some(11),
+        _ => None,
+    }
+}
+
+// Equal temperament with A4 at 440 Hz
+fn frequency(semitone: i32, octave: i32) -> u32 {
+    let from_a4 = semitone - 9 + (octave - 4) * 12;
+    (440.0 * 2f64.powf(from_a4 as f64 / 12.0)).round() as u32
+}
+
+pub fn parse_rtttl(text: &str) -> anyhow::Result<Melody> {
+    let [_, defaults, notes] = text.split(':').collect::<Vec<_>>()[..] else {
+        bail!("expected `name:defaults:notes`");
+    };
+    let (mut duration, mut octave, mut bpm) = (4, 6, 63);
+    for default in defaults.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        let (key, value) = default
+            .split_once('=')
+            .with_context(|| format!("expected `key=value`, found `{default}`"))?;
+        let value: u32 = value
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid value of `{}`", key.trim()))?;
+        match key.trim() {
+            "d" => duration = value,
+            "o" => octave = value,
+            "b" => bpm = value,
+            key => bail!("unknown default `{key}` (expected d, o or b)"),
+        }
+    }
+    ensure!(bpm > 0, "b must be positive");
+    // A beat is a quarter note
+    let whole = Duration::from_secs(60) * 4 / bpm;
+
+    let mut melody = Vec::new();
+    for note in notes.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        let mut chars = note.chars().peekable();
+        let note_duration = take_number(&mut chars).unwrap_or(duration);
+        ensure!(
+            [1, 2, 4, 8, 16, 32, 64].contains(&note_duration),
+            "invalid duration in `{note}`"
+        );
+        let letter = chars.next().map(|c| c.to_ascii_lowercase());
+        let pitch = match letter {
+            Some('p') => None,
+            Some(letter) => {
+                Some(semitone(letter).with_context(|| format!("invalid note `{note}`"))?)
+            }
+            None => bail!("missing note in `{note}`"),
+        };
+        let sharp = chars.next_if_eq(&'#').is_some();
+        let mut dotted = chars.next_if_eq(&'.').is_some();
+        let note_octave = take_number(&mut chars).unwrap_or(octave);
+        ensure!((4..=7).contains(&note_octave), "invalid octave in `{note}`");
+        dotted |= chars.next_if_eq(&'.').is_some();
+        ensure!(chars.next().is_none(), "invalid note `{note}`");
+
+        let mut length = whole / note_duration;
+        if dotted {
+            length += length / 2;
+        }
+        melody.push(Note {
+            frequency: pitch.map(|pitch| frequency(pitch + sharp as i32, note_octave as i32)),
+            duration: length,
+        });
+    }
+    Ok(melody)
+}
+
+// Leading digits of `chars`
+fn take_number(chars: &mut Peekable<Chars>) -> Option<u32> {
+    let mut digits = String::new();
+    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+        digits.push(digit);
+    }
+    digits.parse().ok()
+}
+
+// Game events with a sound, later ones cut off earlier ones but not the
+// other way round
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Effect {
+    Shot,
+    Hit,
+    Laser,
+    Start,
+    Win,
+    Lose,
+}
+
+// Melodies of `config().sound`
+pub struct Melodies {
+    start: Melody,
+    shot: Melody,
+    hit: Melody,
+    laser: Melody,
+    win: Melody,
+    lose: Melody,
+}
+impl Melodies {
+    pub fn new() -> Self {
+        // Checked when the config is loaded
+        let parse = |text: &str| parse_rtttl(text).unwrap();
+        let sound = &config().sound;
+        Self {
+            start: parse(&sound.start),
+            shot: parse(&sound.shot),
+            hit: parse(&sound.hit),
+            laser: parse(&sound.laser),
+            win: parse(&sound.win),
+            lose: parse(&sound.lose),
+        }
+    }
+    fn get(&self, effect: Effect) -> &Melody {
+        match effect {
+            Effect::Start => &self.start,
+            Effect::Shot => &self.shot,
+            Effect::Hit => &self.hit,
+            Effect::Laser => &self.laser,
+            Effect::Win => &self.win,
+            Effect::Lose => &self.lose,
+        }
+    }
+}
+
+// Plays one effect at a time on the buzzer
+pub struct SoundPlayer {
+    buzzer: Box<dyn Buzzer>,
+    melodies: Melodies,
+    playing: Option<Effect>,
+    notes: VecDeque<Note>,
+    // Left of the current note
+    remaining: Duration,
+}
+impl SoundPlayer {
+    pub fn new(buzzer: Box<dyn Buzzer>) -> Self {
+        Self {
+            buzzer,
+            melodies: Melodies::new(),
+            playing: None,
+            notes: VecDeque::new(),
+            remaining: Duration::ZERO,
+        }
+    }
+
+    // Start `effect` from the next tick unless a more important one is playing
+    pub fn play(&mut self, effect: Effect) {
+        if self.playing.is_some_and(|playing| playing > effect) {
+            return;
+        }
+        self.playing = Some(effect);
+        self.notes = self.melodies.get(effect).iter().copied().collect();
+        self.remaining = Duration::ZERO;
+    }
+
+    // Advance by one tick of `tick.tick_ms`
+    pub fn tick(&mut self) {
+        if self.playing.is_none() {
+            return;
+        }
+        if self.remaining.is_zero() {
+            match self.notes.pop_front() {
+                Some(note) => {
+                    self.buzzer.play(&note);
+                    self.remaining = note.duration;
+                }
+                None => {
+                    self.stop();
+                    return;
+                }
+            }
+        }
+        self.remaining = self.remaining.saturating_sub(config().tick.tick_size());
+    }
+
+    // Play the rest of the effect, blocking until it ends
+    pub fn finish(&mut self) {
+        while self.playing.is_some() {
+            self.tick();
+            thread::sleep(config().tick.tick_size());
+        }
+    }
+
+    pub fn stop(&mut self) {
+        if self.playing.take().is_some() {
+            self.notes.clear();
+            self.remaining = Duration::ZERO;
+            self.buzzer.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interface::mock::{BuzzerLog, BuzzerWrite, RecordingBuzzer, TickCounter};
+
+    fn note(frequency: Option<u32>, millis: u64) -> Note {
+        Note {
+            frequency,
+            duration: Duration::from_millis(millis),
+        }
+    }
+
+    #[test]
+    fn default_durations() {
+        // d=4, o=6 and b=63
+        let quarter = Duration::from_secs(60) / 63;
+        let melody = parse_rtttl("defaults::c,p").unwrap();
+        assert_eq!(
+            melody,
+            [
+                Note {
+                    frequency: Some(1047),
+                    duration: quarter
+                },
+                Note {
+                    frequency: None,
+                    duration: quarter
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn explicit_durations_and_dots() {
+        // A whole note is 2s at 120 bpm
+        let melody = parse_rtttl("durations:d=8,o=5,b=120:c,2d,16e,16p,1c,c.,4c.,4c5.").unwrap();
+        assert_eq!(
+            melody,
+            [
+                note(Some(523), 250),
+                note(Some(587), 1000),
+                note(Some(659), 125),
+                note(None, 125),
+                note(Some(523), 2000),
+                note(Some(523), 375),
+                note(Some(523), 750),
+                note(Some(523), 750),
+            ]
+        );
+    }
+
+    #[test]
+    fn sharps_and_octaves() {
+        let melody = parse_rtttl("pitches:d=4,o=4,b=60:a,a#,a5,A5,c#7,h,e#").unwrap();
+        let frequencies: Vec<_> = melody.iter().map(|note| note.frequency).collect();
+        // e# is f
+        assert_eq!(frequencies, [440, 466, 880, 880, 2217, 494, 349].map(Some));
+    }
+
+    #[test]
+    fn malformed() {
+        let cases = [
+            "no sections",
+            "too:many:sections:here",
+            "unknown:q=1:c",
+            "no value:d:c",
+            "bad value:d=four:c",
+            "zero bpm:b=0:c",
+            "bad default duration:d=3:c",
+            "bad duration::3c",
+            "bad note::x",
+            "missing note::8",
+            "low octave::c3",
+            "high octave::c8",
+            "trailing::c#5x",
+        ];
+        for text in cases {
+            assert!(parse_rtttl(text).is_err(), "{text}");
+        }
+    }
+
+    // Player on a recording buzzer with a whole note of 960ms
+    fn recording_player() -> (SoundPlayer, TickCounter, BuzzerLog) {
+        let ticks = TickCounter::new();
+        let buzzer = RecordingBuzzer::new(ticks.clone());
+        let log = buzzer.log();
+        let parse = |text| parse_rtttl(text).unwrap();
+        let melodies = Melodies {
+            start: parse("start:d=4,o=5,b=250:c"),
+            shot: parse("shot:d=8,o=5,b=250:c,e"),
+            hit: parse("hit:d=4,o=5,b=250:g,8p,g"),
+            laser: parse("laser:d=16,o=6,b=250:c"),
+            win: parse("win:d=2,o=5,b=250:c"),
+            lose: parse("lose:d=2,o=4,b=250:c"),
+        };
+        let player = SoundPlayer {
+            buzzer: Box::new(buzzer),
+            melodies,
+            playing: None,
+            notes: VecDeque::new(),
+            remaining: Duration::ZERO,
+        };
+        (player, ticks, log)
+    }
+
+    // Ticks a note sounds for
+    fn ticks_of(millis: u64) -> u64 {
+        let tick = config().tick.tick_size().as_micros() as u64;
+        (millis * 1000).div_ceil(tick)
+    }
+
+    #[test]
+    fn higher_priority_cuts_lower() {
+        let (mut player, ticks, log) = recording_player();
+        let run = |player: &mut SoundPlayer, count| {
+            for _ in 0..count {
+                ticks.advance();
+                player.tick();
+            }
+        };
+        player.play(Effect::Shot);
+        run(&mut player, 10);
+        // The hit cuts the shot off, a shot during the hit does not
+        player.play(Effect::Hit);
+        run(&mut player, 10);
+        player.play(Effect::Shot);
+        run(&mut player, 1000);
+        assert!(player.playing.is_none());
+
+        let (c5, g5) = (Some(523), Some(784));
+        let rest = 11 + ticks_of(240);
+        assert_eq!(
+            *log.borrow(),
+            [
+                BuzzerWrite {
+                    tick: 1,
+                    note: note(c5, 120),
+                },
+                BuzzerWrite {
+                    tick: 11,
+                    note: note(g5, 240),
+                },
+                BuzzerWrite {
+                    tick: rest,
+                    note: note(None, 120),
+                },
+                BuzzerWrite {
+                    tick: rest + ticks_of(120),
+                    note: note(g5, 240),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn notes_in_order() {
+        let (mut player, ticks, log) = recording_player();
+        player.play(Effect::Shot);
+        for _ in 0..1000 {
+            ticks.advance();
+            player.tick();
+        }
+        assert!(player.playing.is_none());
+        assert_eq!(
+            *log.borrow(),
+            [
+                BuzzerWrite {
+                    tick: 1,
+                    note: note(Some(523), 120),
+                },
+                BuzzerWrite {
+                    tick: 1 + ticks_of(120),
+                    note: note(Some(659), 120),
+                },
+            ]
+        );
+    }
+}
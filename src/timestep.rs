@@ -0,0 +1,229 @@
+// Fixed timestep. The game advances one tick per `tick.tick_ms` of real time
+// however long drawing and flushing take, and frames are skipped while it is
+// behind.
+use crate::constant::{MAX_FRAME_SKIP, MAX_LAG};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+// Measured over the last second
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Rates {
+    pub ticks_per_sec: f64,
+    pub frames_per_sec: f64,
+}
+
+// Where the timestep reads the time and waits
+pub trait Clock {
+    fn now(&self) -> Instant;
+    fn sleep(&mut self, duration: Duration);
+}
+
+// Wall clock
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+pub struct Timestep<C: Clock = SystemClock> {
+    clock: C,
+    step: Duration,
+    // When the current tick should end
+    deadline: Instant,
+    // Frames skipped in a row
+    skipped: u32,
+    rates: Rates,
+    window_start: Instant,
+    window_ticks: u32,
+    window_frames: u32,
+}
+impl Timestep {
+    pub fn new(step: Duration) -> Self {
+        Self::with_clock(step, SystemClock)
+    }
+}
+impl<C: Clock> Timestep<C> {
+    pub fn with_clock(step: Duration, clock: C) -> Self {
+        let now = clock.now();
+        Self {
+            clock,
+            step,
+            deadline: now + step,
+            skipped: 0,
+            rates: Rates::default(),
+            window_start: now,
+            window_ticks: 0,
+            window_frames: 0,
+        }
+    }
+
+    // Whether to draw this tick, it is skipped when the tick is already late
+    // unless too many were skipped before
+    pub fn render_due(&mut self) -> bool {
+        if self.clock.now() < self.deadline || self.skipped >= MAX_FRAME_SKIP {
+            self.skipped = 0;
+            self.window_frames += 1;
+            true
+        } else {
+            self.skipped += 1;
+            false
+        }
+    }
+
+    // End the tick, sleeping until it is due. When far behind the lost time is
+    // dropped, so the game slows down instead of running ticks back to back.
+    pub fn wait(&mut self) {
+        self.window_ticks += 1;
+        let now = self.clock.now();
+        let window = now - self.window_start;
+        if window >= Duration::from_secs(1) {
+            self.rates = Rates {
+                ticks_per_sec: self.window_ticks as f64 / window.as_secs_f64(),
+                frames_per_sec: self.window_frames as f64 / window.as_secs_f64(),
+            };
+            self.window_start = now;
+            self.window_ticks = 0;
+            self.window_frames = 0;
+        }
+
+        if now < self.deadline {
+            self.clock.sleep(self.deadline - now);
+        } else if now - self.deadline > MAX_LAG {
+            self.deadline = now;
+        }
+        self.deadline += self.step;
+    }
+
+    pub fn rates(&self) -> Rates {
+        self.rates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, rc::Rc};
+
+    const STEP: Duration = Duration::from_millis(3);
+
+    // Clock which only moves when it is told to or sleeps
+    #[derive(Clone)]
+    struct FakeClock {
+        start: Instant,
+        elapsed: Rc<Cell<Duration>>,
+    }
+    impl FakeClock {
+        fn new() -> Self {
+            Self {
+                start: Instant::now(),
+                elapsed: Rc::default(),
+            }
+        }
+        fn advance(&self, duration: Duration) {
+            self.elapsed.set(self.elapsed.get() + duration);
+        }
+    }
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.start + self.elapsed.get()
+        }
+        fn sleep(&mut self, duration: Duration) {
+            self.advance(duration);
+        }
+    }
+
+    // Run ticks taking `work` each as `run_scenes` does, returning whether
+    // each one rendered
+    fn run(
+        timestep: &mut Timestep<FakeClock>,
+        clock: &FakeClock,
+        work: Duration,
+        ticks: usize,
+    ) -> Vec<bool> {
+        (0..ticks)
+            .map(|_| {
+                clock.advance(work);
+                let render = timestep.render_due();
+                timestep.wait();
+                render
+            })
+            .collect()
+    }
+
+    #[test]
+    fn renders_every_tick_on_time() {
+        let clock = FakeClock::new();
+        let mut timestep = Timestep::with_clock(STEP, clock.clone());
+        let renders = run(&mut timestep, &clock, Duration::from_millis(1), 1000);
+        assert!(renders.iter().all(|render| *render));
+        // It slept for the rest of every tick
+        assert_eq!(clock.elapsed.get(), STEP * 1000);
+    }
+
+    #[test]
+    fn skips_renders_when_behind() {
+        let clock = FakeClock::new();
+        let mut timestep = Timestep::with_clock(STEP, clock.clone());
+        // Ticks taking twice their time, but less than `MAX_LAG` behind
+        let ticks = 40;
+        let renders = run(&mut timestep, &clock, STEP * 2, ticks);
+        assert!(clock.elapsed.get() - STEP * ticks as u32 <= MAX_LAG);
+        // Every tick ends late, so only the render forced after
+        // `MAX_FRAME_SKIP` skipped ones is drawn
+        let skip = MAX_FRAME_SKIP as usize;
+        let expected: Vec<bool> = (0..ticks).map(|tick| tick % (skip + 1) == skip).collect();
+        assert_eq!(renders, expected);
+    }
+
+    #[test]
+    fn drops_lag_beyond_max_lag() {
+        let clock = FakeClock::new();
+        let mut timestep = Timestep::with_clock(STEP, clock.clone());
+        run(&mut timestep, &clock, Duration::ZERO, 10);
+        // One tick stalls for longer than `MAX_LAG`
+        run(&mut timestep, &clock, MAX_LAG * 2, 1);
+        // The ticks after it are on time again instead of catching up
+        let before = clock.elapsed.get();
+        let renders = run(&mut timestep, &clock, Duration::ZERO, 10);
+        assert!(renders.iter().all(|render| *render));
+        assert_eq!(clock.elapsed.get() - before, STEP * 10);
+    }
+
+    #[test]
+    fn rates_over_a_second() {
+        let clock = FakeClock::new();
+        let mut timestep = Timestep::with_clock(STEP, clock.clone());
+        // Every third tick runs late, until the first second is measured
+        let (mut ticks, mut frames) = (0, 0);
+        while timestep.rates().ticks_per_sec == 0.0 {
+            let work = if ticks % 3 == 0 {
+                STEP * 2
+            } else {
+                Duration::ZERO
+            };
+            frames += run(&mut timestep, &clock, work, 1)[0] as u32;
+            ticks += 1;
+        }
+        let rates = timestep.rates();
+        let seconds = clock.elapsed.get().as_secs_f64();
+        assert!(frames < ticks, "{frames} of {ticks}");
+        assert!(
+            (rates.ticks_per_sec - ticks as f64 / seconds).abs() < 2.0,
+            "{rates:?}"
+        );
+        assert!(
+            (rates.frames_per_sec - frames as f64 / seconds).abs() < 2.0,
+            "{rates:?}"
+        );
+        assert!(
+            (rates.ticks_per_sec - 1.0 / STEP.as_secs_f64()).abs() < 2.0,
+            "{rates:?}"
+        );
+    }
+}
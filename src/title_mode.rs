@@ -0,0 +1,84 @@
+use crate::config::config;
+use crate::constant::TITLE_TIMEOUT;
+use crate::interface::{Display, Interfaces};
+use crate::scene::{Input, Press, Presses, Scene, SceneId, Session, Transition};
+use device_query::Keycode;
+use display_interface::DisplayError;
+use embedded_graphics::{
+    mono_font::{
+        ascii::{FONT_10X20, FONT_6X10},
+        MonoTextStyle,
+    },
+    pixelcolor::BinaryColor,
+    prelude::*,
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+};
+
+// Title scene
+// Button 2 or Enter starts a match and button 1 or Tab opens the menu. A match
+// starts by itself when nothing is pressed for a while, so the game runs
+// unattended as well.
+pub struct TitleScene {
+    presses: Presses,
+    // Ticks since anything was pressed
+    idle: u64,
+}
+impl TitleScene {
+    pub fn new() -> Self {
+        Self {
+            presses: Presses::new(),
+            idle: 0,
+        }
+    }
+}
+
+impl<D: Display> Scene<D> for TitleScene {
+    fn update(
+        &mut self,
+        _interfaces: &mut Interfaces<D>,
+        _session: &mut Session,
+        input: &Input,
+    ) -> anyhow::Result<Transition> {
+        let [button1, button2] = self.presses.read(&input.button_events);
+        if input.keycodes.is_empty() && input.button_events.is_empty() {
+            self.idle += 1;
+        } else {
+            self.idle = 0;
+        }
+        Ok(
+            if button2 == Press::Short || input.pressed.contains(&Keycode::Enter) {
+                Transition::To(SceneId::Match)
+            } else if button1 == Press::Short || input.pressed.contains(&Keycode::Tab) {
+                Transition::To(SceneId::Menu)
+            } else if self.idle >= config().tick.ticks(TITLE_TIMEOUT) {
+                Transition::To(SceneId::Match)
+            } else {
+                Transition::Stay
+            },
+        )
+    }
+
+    fn render(&self, _session: &Session, display: &mut D) -> Result<(), DisplayError> {
+        let size = display.size();
+        let center_style = TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Middle)
+            .build();
+        display.clear(BinaryColor::Off)?;
+        Text::with_text_style(
+            "VShooter",
+            Point::new(size.width as i32 / 2, size.height as i32 / 3),
+            MonoTextStyle::new(&FONT_10X20, BinaryColor::On),
+            center_style,
+        )
+        .draw(display)?;
+        Text::with_text_style(
+            "B2 play  B1 menu",
+            Point::new(size.width as i32 / 2, size.height as i32 * 3 / 4),
+            MonoTextStyle::new(&FONT_6X10, BinaryColor::On),
+            center_style,
+        )
+        .draw(display)?;
+        Ok(())
+    }
+}